@@ -6,17 +6,122 @@
 */
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::hash::Hash;
+use std::iter::FromIterator;
 
-#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
-pub struct ID(usize);
+// An ID is an index plus a generation.
+// When an index is recycled for a new item (see IDManager3), the
+// generation is bumped, so a stale ID for the old item no longer
+// compares equal to the new one -- it just finds nothing.
+//
+// IDs are ordered by index (the derived order compares fields in order,
+// and the generation only breaks ties), which makes range scans possible.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ID {
+    index: usize,
+    generation: u32,
+}
 impl ID {
     // for convenience, function to step to the next ID:
     pub fn step(&mut self) {
-        self.0 += 1;
+        self.index += 1;
+    }
+    // Just the index, e.g. for array indices. Two generations of the
+    // same index look alike here, so this can't be turned back into an ID.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    // Conversions to and from a plain number, e.g. for a custom on-disk
+    // format. Both fields are packed in: the index in the high bits and
+    // the generation in the low 32, so a recycled ID round-trips too.
+    // A u128 always has room for both, so to_bits can't fail; from_bits
+    // rejects numbers whose index doesn't fit in a usize.
+    // Nothing stops you from making an ID that was never handed out; the
+    // managers' get_item simply returns None for it.
+    pub fn to_bits(&self) -> u128 {
+        (self.index as u128) << u32::BITS | self.generation as u128
+    }
+    pub fn from_bits(bits: u128) -> Option<ID> {
+        let index = usize::try_from(bits >> u32::BITS).ok()?;
+        Some(ID { index, generation: bits as u32 })
+    }
+    // The same packing in a usize, which only has room for small indices
+    // (below 2^32 on a 64-bit target, and only 0 on a 32-bit one), so
+    // as_usize gives None for any bigger index rather than losing bits.
+    pub fn as_usize(&self) -> Option<usize> {
+        usize::try_from(self.to_bits()).ok()
+    }
+    pub fn from_usize(n: usize) -> Option<ID> {
+        ID::from_bits(n as u128)
+    }
+    // Like step, but None instead of overflowing
+    fn checked_next(self) -> Option<ID> {
+        let index = self.index.checked_add(1)?;
+        Some(ID { index, generation: self.generation })
+    }
+    // The ID for the next occupant of the same index
+    fn next_generation(self) -> ID {
+        ID { index: self.index, generation: self.generation.wrapping_add(1) }
+    }
+}
+
+// Short form for logs: #42
+// (a recycled ID also shows its generation, e.g. #42@1)
+impl fmt::Display for ID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.index)?;
+        if self.generation != 0 {
+            write!(f, "@{}", self.generation)?;
+        }
+        Ok(())
+    }
+}
+
+// Error returned when every possible ID has been handed out
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IdExhausted;
+
+impl fmt::Display for IdExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no more IDs available")
     }
 }
 
+impl std::error::Error for IdExhausted {}
+
+// Errors from inserting an item under a given ID (see
+// IDManager3::insert_with_id)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InsertError {
+    // Another item already uses this index (under the given ID)
+    Occupied(ID),
+    // The item is already stored, under the given ID
+    DuplicateItem(ID),
+    // No ID could ever follow this one
+    Exhausted,
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertError::Occupied(id) => {
+                write!(f, "ID {} is already in use", id)
+            }
+            InsertError::DuplicateItem(id) => {
+                write!(f, "item is already stored as {}", id)
+            }
+            InsertError::Exhausted => {
+                write!(f, "no more IDs available after this one")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InsertError {}
+
 /*
     Desired Functionality:
     1. Get the ID for an item &T.
@@ -69,6 +174,14 @@ where
         self.id_to_item.get(&id)
     }
 
+    // Number of items currently stored (not the number ever assigned)
+    pub fn len(&self) -> usize {
+        self.id_to_item.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.id_to_item.is_empty()
+    }
+
     // Insertion and deletion
     pub fn insert(&mut self, item: T) -> ID {
         let id = self.next_id;
@@ -84,7 +197,6 @@ where
             self.item_to_id.remove(item);
             true
         } else {
-            eprintln!("Warning: tried to delete nonexistent item");
             false
         }
     }
@@ -111,6 +223,8 @@ where
     ========== ATTEMPT #2 ==========
 
     Try to remove the Clone bound
+
+    (Each T is stored in a Box, see the notes after the impl for why.)
 */
 
 pub struct IDManager2<'a, T>
@@ -118,7 +232,7 @@ where
     T: Eq + Hash,
 {
     next_id: ID,
-    id_to_item: HashMap<ID, T>,
+    id_to_item: HashMap<ID, Box<T>>,
     item_to_id: HashMap<&'a T, ID>,
 }
 
@@ -150,7 +264,26 @@ where
         self.item_to_id.get(item).copied()
     }
     pub fn get_item(&self, id: ID) -> Option<&T> {
-        self.id_to_item.get(&id)
+        self.id_to_item.get(&id).map(|x| x.as_ref())
+    }
+
+    // Number of items currently stored (not the number ever assigned)
+    pub fn len(&self) -> usize {
+        self.id_to_item.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.id_to_item.is_empty()
+    }
+
+    // Sanity check for tests: the two maps are exact mirror images
+    pub fn check_invariant(&self) -> bool {
+        self.id_to_item.len() == self.item_to_id.len()
+            && self.id_to_item.iter().all(|(id, item)| {
+                self.item_to_id.get(item.as_ref()) == Some(id)
+            })
+            && self.item_to_id.iter().all(|(item, id)| {
+                self.id_to_item.get(id).map(|x| x.as_ref()) == Some(*item)
+            })
     }
 
     // Insertion and deletion
@@ -158,7 +291,7 @@ where
         // **Hard Part!**
         let id = self.next_id;
 
-        self.id_to_item.insert(id, item);
+        self.id_to_item.insert(id, Box::new(item));
 
         // This line in particular is just unsafe
         // Use case for unsafe code:
@@ -166,9 +299,10 @@ where
         // - Localized: unsafe reference is only in this block
         // How to do this with unsafe code?
 
-        // First make a raw pointer
+        // First make a raw pointer -- to the T on the heap, not into
+        // the HashMap's own storage
         // (unwrap OK -- just inserted, so I know it exists)
-        let item_ref_raw: *const T = self.id_to_item.get(&id).unwrap();
+        let item_ref_raw: *const T = self.id_to_item.get(&id).unwrap().as_ref();
 
         // Convert the pointer to a reference and put it in
         // the other HashMap
@@ -185,11 +319,12 @@ where
     pub fn delete(&mut self, item: &T) -> bool {
         // true if item existed, false if not
         if let Some(id) = self.get_id(item) {
-            self.id_to_item.remove(&id);
+            // Order matters: removing from item_to_id hashes and compares
+            // the stored &T, so it must happen while the Box is alive
             self.item_to_id.remove(item);
+            self.id_to_item.remove(&id);
             true
         } else {
-            eprintln!("Warning: tried to delete nonexistent item");
             false
         }
     }
@@ -200,13 +335,16 @@ where
 
     But it's precarious: I am relying on some assumptions about memory.
 
-    I'm assuming that the memory location of the T in the first hash map
-    never changes.
+    I'm assuming that the memory location of the T never changes.
 
-    To really be safe, we have to read the HashMap API and make sure that
-    the HashMap isn't moved...
-    - maybe we call a method at the beginning when we create the HashMap
-      to ensure its size statically...
+    If we stored the T directly in the first HashMap, that would be false:
+    when the map grows it rehashes, moving every value to a new
+    allocation, and every &T in item_to_id would be left dangling.
+
+    That's why each T lives in a Box: a rehash moves the Box (a pointer),
+    but the T it points to stays put on the heap until we remove it.
+    We still have to be careful on delete (see above), and nothing in
+    the types checks any of this for us.
 */
 
 /*
@@ -240,32 +378,52 @@ where
     - High-quality stdlib-worthy implementation
 */
 
-use std::ops::Deref;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 
-pub struct IDManager3<T>
+// Deleted IDs go on a free list and are handed out again by later
+// inserts, which keeps the ID space dense (next_id only moves when the
+// free list is empty). As a consequence IDs are no longer monotonic:
+// a new item may get a smaller ID than an older one.
+//
+// The free list holds each freed index with its generation already
+// bumped, so it doubles as the record of the current generation per
+// index. Anyone still holding the old ID gets None from get_item
+// instead of silently reading the new occupant (the "ABA" problem).
+//
+// S is the hasher used by both maps. It defaults to RandomState like
+// HashMap does, but any S: BuildHasher + Default can be plugged in,
+// e.g. a fixed-seed hasher for reproducible runs.
+pub struct IDManager3<T, S = RandomState>
 where
     T: Eq + Hash,
 {
     next_id: ID,
-    id_to_item: HashMap<ID, Rc<T>>,
-    item_to_id: HashMap<Rc<T>, ID>,
+    free_ids: Vec<ID>,
+    id_to_item: HashMap<ID, Rc<T>, S>,
+    item_to_id: HashMap<Rc<T>, ID, S>,
 }
 
-impl<T> Default for IDManager3<T>
+impl<T, S> Default for IDManager3<T, S>
 where
     T: Eq + Hash,
+    S: BuildHasher + Default,
 {
     fn default() -> Self {
         // Empty maps
         Self {
             next_id: Default::default(),
+            free_ids: Default::default(),
             id_to_item: Default::default(),
             item_to_id: Default::default(),
         }
     }
 }
 
+// Like HashMap, the plain constructors are only for the default hasher.
+// With a custom hasher, use IDManager3::default() instead.
 impl<T> IDManager3<T>
 where
     T: Eq + Hash,
@@ -275,6 +433,27 @@ where
         Default::default()
     }
 
+    // Pre-size both maps, to avoid rehashing while bulk loading
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            next_id: Default::default(),
+            free_ids: Default::default(),
+            id_to_item: HashMap::with_capacity(capacity),
+            item_to_id: HashMap::with_capacity(capacity),
+        }
+    }
+}
+
+impl<T, S> IDManager3<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn reserve(&mut self, additional: usize) {
+        self.id_to_item.reserve(additional);
+        self.item_to_id.reserve(additional);
+    }
+
     // The bidirectional map
     pub fn get_id(&self, item: &T) -> Option<ID> {
         self.item_to_id.get(item).copied()
@@ -284,33 +463,2034 @@ where
         self.id_to_item.get(&id).map(|x| x.deref())
     }
 
+    // Membership checks, without handing out a reference
+    pub fn contains_id(&self, id: ID) -> bool {
+        self.id_to_item.contains_key(&id)
+    }
+    pub fn contains_item(&self, item: &T) -> bool {
+        self.item_to_id.contains_key(item)
+    }
+
+    // Walk all (ID, &T) pairs, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (ID, &T)> {
+        self.id_to_item.iter().map(|(&id, item)| (id, item.deref()))
+    }
+    // Just the items, or just the IDs (like HashMap's values and keys)
+    pub fn items(&self) -> impl Iterator<Item = &T> {
+        self.id_to_item.values().map(|item| item.deref())
+    }
+    pub fn ids(&self) -> impl Iterator<Item = ID> + '_ {
+        self.id_to_item.keys().copied()
+    }
+    // The items with lo <= ID < hi, sorted by ID.
+    // A HashMap has no order to exploit, so this scans every item and
+    // sorts the matches.
+    pub fn range(&self, lo: ID, hi: ID) -> impl Iterator<Item = (ID, &T)> {
+        let mut matches: Vec<(ID, &T)> =
+            self.iter().filter(|&(id, _)| lo <= id && id < hi).collect();
+        matches.sort_unstable_by_key(|&(id, _)| id);
+        matches.into_iter()
+    }
+
+    // Hand out a clone of the internal Rc<T>, so the item can outlive its
+    // entry in the manager.
+    // Note: this bumps the strong count (see ref_count). While such a
+    // clone is alive, the item can't be moved back out of its Rc:
+    // delete_by_id returns None for it, into_iter skips it, and
+    // get_item_mut refuses it.
+    pub fn get_item_rc(&self, id: ID) -> Option<Rc<T>> {
+        self.id_to_item.get(&id).cloned()
+    }
+
+    // How many Rc's to the item exist *outside* the manager
+    // (the ones held by id_to_item and item_to_id are not counted).
+    // Normally the manager holds exactly two, but after a duplicate
+    // insert, item_to_id only keeps the first Rc for an item, so check
+    // which Rc it actually holds rather than assuming.
+    pub fn ref_count(&self, id: ID) -> Option<usize> {
+        let item_ref = self.id_to_item.get(&id)?;
+        let mirrored = self
+            .item_to_id
+            .get_key_value(item_ref.deref())
+            .is_some_and(|(key, _)| Rc::ptr_eq(key, item_ref));
+        Some(Rc::strong_count(item_ref) - 1 - mirrored as usize)
+    }
+
+    // Mutable access to an item.
+    //
+    // Hazard: item_to_id is keyed by the item's hash, so mutating an item
+    // in place would normally leave it filed under its *old* hash. To
+    // avoid that, we hand out a guard instead of a bare &mut T: the item
+    // is taken out of item_to_id while the guard lives, and put back
+    // (under its new hash) when the guard is dropped.
+    //
+    // Returns None if the ID is unknown, or if the item is aliased by some
+    // other Rc -- Rc::get_mut refuses to hand out &mut T in that case.
+    // Mutating an item into a duplicate of another item is a logic error,
+    // just like inserting a duplicate.
+    pub fn get_item_mut(&mut self, id: ID) -> Option<ItemMut<'_, T, S>> {
+        let item_ref = self.id_to_item.get(&id)?;
+        // Drop the reverse map's Rc, so id_to_item holds the only one
+        self.item_to_id.remove(item_ref.deref());
+
+        let item_ref = self.id_to_item.get_mut(&id).unwrap();
+        if Rc::get_mut(item_ref).is_none() {
+            let item_ref = item_ref.clone();
+            self.item_to_id.insert(item_ref, id);
+            return None;
+        }
+        Some(ItemMut { manager: self, id })
+    }
+
+    // Number of items currently stored (not the number ever assigned)
+    pub fn len(&self) -> usize {
+        self.id_to_item.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.id_to_item.is_empty()
+    }
+
+    // Sanity check for tests: the two maps are exact mirror images
+    pub fn check_invariant(&self) -> bool {
+        self.id_to_item.len() == self.item_to_id.len()
+            && self
+                .id_to_item
+                .iter()
+                .all(|(id, item)| self.item_to_id.get(item) == Some(id))
+            && self
+                .item_to_id
+                .iter()
+                .all(|(item, id)| self.id_to_item.get(id) == Some(item))
+    }
+
     // Insertion and deletion
     pub fn insert(&mut self, item: T) -> ID {
+        self.try_insert(item).expect("IDManager3 ran out of IDs")
+    }
+    // Like insert, but reports running out of IDs instead of panicking
+    // (or, in release mode, silently wrapping around to ID 0).
+    pub fn try_insert(&mut self, item: T) -> Result<ID, IdExhausted> {
         // **Hard Part!**
-        let id = self.next_id;
-
-        let item_ref = Rc::new(item);
+        self.try_insert_rc(Rc::new(item))
+    }
+    // Auxiliary method: file an item that is already in an Rc
+    fn try_insert_rc(&mut self, item_ref: Rc<T>) -> Result<ID, IdExhausted> {
+        let id = self.fresh_id()?;
 
         // Notice that T doesn't implement clone
         // But Rc<T> does!
         self.id_to_item.insert(id, item_ref.clone());
         self.item_to_id.insert(item_ref, id);
 
-        self.next_id.step();
-        id
+        Ok(id)
+    }
+    // Insert an item under an ID chosen by the caller, e.g. when loading
+    // data whose IDs were assigned elsewhere.
+    // Fails if the item is already stored, or if the index is taken, even
+    // by a different generation: two live IDs sharing an index would
+    // confuse the free list. (Checking that means scanning every ID, so
+    // this is O(n), unlike insert.)
+    // Afterwards, automatically assigned IDs never collide with id:
+    // next_id moves past it, and its index leaves the free list.
+    pub fn insert_with_id(
+        &mut self,
+        id: ID,
+        item: T,
+    ) -> Result<(), InsertError> {
+        if let Some(existing) = self.get_id(&item) {
+            return Err(InsertError::DuplicateItem(existing));
+        }
+        if let Some(&taken) =
+            self.id_to_item.keys().find(|k| k.index == id.index)
+        {
+            return Err(InsertError::Occupied(taken));
+        }
+        if id.index >= self.next_id.index {
+            self.next_id = ID { index: id.index, ..self.next_id }
+                .checked_next()
+                .ok_or(InsertError::Exhausted)?;
+        }
+        self.free_ids.retain(|free| free.index != id.index);
+
+        let item_ref = Rc::new(item);
+        self.id_to_item.insert(id, item_ref.clone());
+        self.item_to_id.insert(item_ref, id);
+        Ok(())
+    }
+    // Insert a batch, returning the IDs in the same order as the items
+    pub fn insert_many<I: IntoIterator<Item = T>>(
+        &mut self,
+        items: I,
+    ) -> Vec<ID> {
+        let items = items.into_iter();
+        self.reserve(items.size_hint().0);
+        items.map(|item| self.insert(item)).collect()
+    }
+    // Like insert, but reuses the existing ID if the item is already
+    // present. (Plain insert on a duplicate item leaves a second entry
+    // behind in id_to_item and overwrites the one in item_to_id.)
+    pub fn get_or_insert(&mut self, item: T) -> ID {
+        self.entry(item).or_insert()
+    }
+    // Look up an item, getting back either its ID or a handle that can
+    // insert it, as with HashMap::entry
+    pub fn entry(&mut self, item: T) -> Entry<'_, T, S> {
+        match self.get_id(&item) {
+            Some(id) => Entry::Occupied(id),
+            None => Entry::Vacant(VacantEntry { manager: self, item }),
+        }
     }
     pub fn delete(&mut self, item: &T) -> bool {
         // true if item existed, false if not
         if let Some(id) = self.get_id(item) {
-            self.id_to_item.remove(&id);
-            // more type magic, &T auto converted to Rc<T>
-            self.item_to_id.remove(item);
+            self.remove_by_id(id);
             true
         } else {
-            eprintln!("Warning: tried to delete nonexistent item");
             false
         }
     }
+
+    // Delete by ID and hand the item back.
+    // Returns None if the ID is unknown, or if the item is still shared
+    // by some other Rc (it is removed from the manager either way).
+    pub fn delete_by_id(&mut self, id: ID) -> Option<T> {
+        let item_ref = self.remove_by_id(id)?;
+        Rc::try_unwrap(item_ref).ok()
+    }
+
+    // Auxiliary method: take an entry out of both maps, returning the
+    // Rc that was stored in id_to_item.
+    fn remove_by_id(&mut self, id: ID) -> Option<Rc<T>> {
+        let item_ref = self.id_to_item.remove(&id)?;
+        // more type magic, &T auto converted to Rc<T>
+        self.item_to_id.remove(item_ref.deref());
+        self.free_ids.push(id.next_generation());
+        Some(item_ref)
+    }
+
+    // Auxiliary method: recycle a freed ID if there is one,
+    // otherwise step next_id (checking for overflow).
+    fn fresh_id(&mut self) -> Result<ID, IdExhausted> {
+        if let Some(id) = self.free_ids.pop() {
+            return Ok(id);
+        }
+        let id = self.next_id;
+        self.next_id = id.checked_next().ok_or(IdExhausted)?;
+        Ok(id)
+    }
+
+    // Remove every item, keeping the allocated capacity of both maps.
+    // next_id is deliberately NOT reset: IDs handed out before the clear
+    // stay dead forever, so a stale ID can never alias a fresh item.
+    // (Their generations are remembered in next_id, for compact.)
+    pub fn clear(&mut self) {
+        self.next_id.generation = self.max_generation();
+        self.id_to_item.clear();
+        self.item_to_id.clear();
+    }
+
+    // Move every item of other into self. Items self already has keep
+    // their ID here, the rest get fresh IDs.
+    // Returns the old ID -> new ID mapping, so callers can fix up any IDs
+    // from other that they are still holding on to.
+    pub fn merge(&mut self, other: IDManager3<T, S>) -> HashMap<ID, ID> {
+        let IDManager3 { id_to_item, item_to_id, .. } = other;
+        drop(item_to_id);
+
+        self.reserve(id_to_item.len());
+        let mut remap = HashMap::with_capacity(id_to_item.len());
+        for (old_id, item_ref) in id_to_item {
+            // No need to unwrap the Rc: it moves over as is
+            let new_id = match self.get_id(&item_ref) {
+                Some(id) => id,
+                None => self
+                    .try_insert_rc(item_ref)
+                    .expect("IDManager3 ran out of IDs"),
+            };
+            remap.insert(old_id, new_id);
+        }
+        remap
+    }
+
+    // Move every item out, leaving an empty (but reusable) manager.
+    // Same Rc dance as into_iter: item_to_id is emptied up front, so each
+    // Rc left in id_to_item is the last one and try_unwrap succeeds
+    // (items still shared via get_item_rc are skipped).
+    // Both maps are empty afterwards even if the iterator is dropped
+    // early, and like clear, next_id keeps counting.
+    pub fn drain(&mut self) -> impl Iterator<Item = (ID, T)> + '_ {
+        self.next_id.generation = self.max_generation();
+        self.item_to_id.clear();
+        self.id_to_item.drain().filter_map(|(id, item_ref)| {
+            Rc::try_unwrap(item_ref).ok().map(|item| (id, item))
+        })
+    }
+
+    // Keep only the items for which f returns true.
+    // We can't remove from id_to_item while iterating over it, so first
+    // collect the doomed IDs, then remove each one from both maps.
+    pub fn retain<F: FnMut(ID, &T) -> bool>(&mut self, mut f: F) {
+        let doomed: Vec<ID> = self
+            .id_to_item
+            .iter()
+            .filter(|(&id, item)| !f(id, item))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in doomed {
+            self.remove_by_id(id);
+        }
+    }
+
+    // Renumber the items 0..len, closing the gaps left by deletions, and
+    // forget about the free list.
+    // Surviving items keep their relative order. Returns the old ID ->
+    // new ID mapping, as merge does: every ID held from before the
+    // compaction must be translated, since an old ID may now belong to
+    // a different item.
+    // Indices get reused, so every new ID (both the renumbered ones and
+    // those handed out later) uses a generation above any issued so far:
+    // an old ID, even a deleted one, can never resolve to anything.
+    pub fn compact(&mut self) -> HashMap<ID, ID> {
+        let generation = self.max_generation().wrapping_add(1);
+        let mut entries: Vec<(ID, Rc<T>)> = self.id_to_item.drain().collect();
+        entries.sort_by_key(|(id, _)| id.index);
+        self.item_to_id.clear();
+        self.free_ids.clear();
+
+        let mut remap = HashMap::with_capacity(entries.len());
+        for (index, (old_id, item_ref)) in entries.into_iter().enumerate() {
+            let new_id = ID { index, generation };
+            self.id_to_item.insert(new_id, item_ref.clone());
+            self.item_to_id.insert(item_ref, new_id);
+            remap.insert(old_id, new_id);
+        }
+        self.next_id = ID { index: self.len(), generation };
+        remap
+    }
+
+    // Auxiliary method: the highest generation any ID handed out so far
+    // can have. Live IDs are in id_to_item, deleted ones are covered by
+    // the (already bumped) free list, and clear/drain fold the IDs they
+    // throw away into next_id.
+    fn max_generation(&self) -> u32 {
+        self.id_to_item
+            .keys()
+            .chain(&self.free_ids)
+            .map(|id| id.generation)
+            .fold(self.next_id.generation, u32::max)
+    }
+
+    // Checkpoint the current state, to go back to it later with restore.
+    // Cheap: the items aren't cloned, only their Rc's.
+    // Note that while a snapshot is alive its Rc's count as outside
+    // references (see ref_count), so e.g. get_item_mut refuses the items
+    // it shares.
+    pub fn snapshot(&self) -> Snapshot<T> {
+        Snapshot {
+            next_id: self.next_id,
+            free_ids: self.free_ids.clone(),
+            items: self
+                .id_to_item
+                .iter()
+                .map(|(&id, item)| (id, item.clone()))
+                .collect(),
+        }
+    }
+    // Go back to the state at snap, IDs and all
+    pub fn restore(&mut self, snap: Snapshot<T>) {
+        self.id_to_item.clear();
+        self.item_to_id.clear();
+        self.next_id = snap.next_id;
+        self.free_ids = snap.free_ids;
+        for (id, item_ref) in snap.items {
+            self.id_to_item.insert(id, item_ref.clone());
+            self.item_to_id.insert(item_ref, id);
+        }
+    }
+}
+
+// Saved state of an IDManager3, see IDManager3::snapshot
+pub struct Snapshot<T> {
+    next_id: ID,
+    free_ids: Vec<ID>,
+    items: Vec<(ID, Rc<T>)>,
+}
+
+// Guard returned by get_item_mut: derefs to the item, and re-files it
+// in item_to_id when dropped.
+pub struct ItemMut<'a, T, S = RandomState>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    manager: &'a mut IDManager3<T, S>,
+    id: ID,
+}
+
+impl<'a, T, S> Deref for ItemMut<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.manager.id_to_item[&self.id]
+    }
+}
+
+impl<'a, T, S> DerefMut for ItemMut<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        // unwraps OK: get_item_mut checked the item exists and is not
+        // aliased, and nothing can clone it while we borrow the manager
+        let item_ref = self.manager.id_to_item.get_mut(&self.id).unwrap();
+        Rc::get_mut(item_ref).unwrap()
+    }
+}
+
+impl<'a, T, S> Drop for ItemMut<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        let item_ref = self.manager.id_to_item[&self.id].clone();
+        self.manager.item_to_id.insert(item_ref, self.id);
+    }
+}
+
+// Result of IDManager3::entry
+pub enum Entry<'a, T, S = RandomState>
+where
+    T: Eq + Hash,
+{
+    // The item is already stored, under this ID
+    Occupied(ID),
+    // The item is not stored yet
+    Vacant(VacantEntry<'a, T, S>),
+}
+
+pub struct VacantEntry<'a, T, S = RandomState>
+where
+    T: Eq + Hash,
+{
+    manager: &'a mut IDManager3<T, S>,
+    item: T,
+}
+
+impl<'a, T, S> Entry<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    // The existing ID, or the ID of the newly inserted item
+    pub fn or_insert(self) -> ID {
+        match self {
+            Entry::Occupied(id) => id,
+            Entry::Vacant(entry) => entry.insert(),
+        }
+    }
+}
+
+impl<'a, T, S> VacantEntry<'a, T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    // The item that would be inserted
+    pub fn item(&self) -> &T {
+        &self.item
+    }
+    // Assign a new ID to the item
+    pub fn insert(self) -> ID {
+        self.manager.insert(self.item)
+    }
+}
+
+/*
+    Consuming an IDManager3 to get the items back out.
+
+    Each item lives in an Rc shared between the two maps, so
+    Rc::try_unwrap only succeeds once we are holding the *last* Rc.
+    Ordering matters: item_to_id has to be dropped first, which leaves
+    id_to_item with a strong count of one for every item.
+*/
+
+use std::collections::hash_map;
+
+pub struct IntoIter<T> {
+    inner: hash_map::IntoIter<ID, Rc<T>>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = (ID, T);
+    fn next(&mut self) -> Option<(ID, T)> {
+        // An item that is still shared (e.g. via get_item_rc) can't be
+        // moved out of its Rc, so it is skipped rather than panicking.
+        for (id, item) in &mut self.inner {
+            if let Ok(item) = Rc::try_unwrap(item) {
+                return Some((id, item));
+            }
+        }
+        None
+    }
+}
+
+impl<T, S> IntoIterator for IDManager3<T, S>
+where
+    T: Eq + Hash,
+{
+    type Item = (ID, T);
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> IntoIter<T> {
+        let IDManager3 { id_to_item, item_to_id, .. } = self;
+        // Must come first: releases the second Rc to every item
+        drop(item_to_id);
+        IntoIter { inner: id_to_item.into_iter() }
+    }
+}
+
+// Collecting into a manager assigns IDs in iteration order
+impl<T, S> FromIterator<T> for IDManager3<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(items: I) -> Self {
+        let mut manager = Self::default();
+        manager.insert_many(items);
+        manager
+    }
+}
+
+// Prints next_id and the {ID: item} entries. item_to_id is skipped,
+// it holds the same information backwards.
+impl<T, S> fmt::Debug for IDManager3<T, S>
+where
+    T: Eq + Hash + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IDManager3")
+            .field("next_id", &self.next_id)
+            .field("items", &self.id_to_item)
+            .finish()
+    }
+}
+
+// Careful: two managers are equal if they hold the same *set of items*.
+// Which ID each item got (and next_id, and the free list) is ignored, so
+// equal managers may well disagree about what a given ID refers to.
+impl<T, S> PartialEq for IDManager3<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.items().all(|item| other.contains_item(item))
+    }
+}
+impl<T, S> Eq for IDManager3<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+}
+
+/*
+    Serde support (behind the "serde" feature)
+
+    Only next_id and the id_to_item entries are written out; item_to_id
+    is rebuilt from them on the way back in, so the two maps can't
+    disagree and each T is only serialized once.
+    The entries are written as a list of (ID, T) pairs rather than a map,
+    because formats like JSON only allow string keys.
+
+    The free list is not saved: after a round trip, deleted IDs are
+    simply never reused, which is always safe. (Its generations are
+    folded into next_id, so compact still stays clear of them.)
+*/
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct IDManager3Ser<'a, T> {
+    next_id: ID,
+    items: Vec<(ID, &'a T)>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct IDManager3De<T> {
+    next_id: ID,
+    items: Vec<(ID, T)>,
+}
+
+#[cfg(feature = "serde")]
+impl<T, S> serde::Serialize for IDManager3<T, S>
+where
+    T: Eq + Hash + serde::Serialize,
+    S: BuildHasher,
+{
+    fn serialize<Ser: serde::Serializer>(
+        &self,
+        serializer: Ser,
+    ) -> Result<Ser::Ok, Ser::Error> {
+        let repr = IDManager3Ser {
+            next_id: ID { generation: self.max_generation(), ..self.next_id },
+            items: self.iter().collect(),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T, S> serde::Deserialize<'de> for IDManager3<T, S>
+where
+    T: Eq + Hash + serde::Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    fn deserialize<De: serde::Deserializer<'de>>(
+        deserializer: De,
+    ) -> Result<Self, De::Error> {
+        use serde::de::Error;
+        use std::collections::HashSet;
+
+        let repr = IDManager3De::<T>::deserialize(deserializer)?;
+        let mut manager = Self::default();
+        manager.reserve(repr.items.len());
+        manager.next_id = repr.next_id;
+        // Same rule as insert_with_id: one live ID per index
+        let mut indices = HashSet::with_capacity(repr.items.len());
+        for (id, item) in repr.items {
+            if id.index >= repr.next_id.index {
+                return Err(De::Error::custom("ID beyond next_id"));
+            }
+            if !indices.insert(id.index) {
+                return Err(De::Error::custom("two IDs with the same index"));
+            }
+            let item_ref = Rc::new(item);
+            if manager.item_to_id.insert(item_ref.clone(), id).is_some()
+                || manager.id_to_item.insert(id, item_ref).is_some()
+            {
+                return Err(De::Error::custom("duplicate ID or item"));
+            }
+        }
+        Ok(manager)
+    }
+}
+
+#[test]
+fn test_len_after_delete() {
+    let mut m1 = IDManager1::new();
+    let mut m2 = IDManager2::new();
+    let mut m3 = IDManager3::new();
+    assert!(m1.is_empty() && m2.is_empty() && m3.is_empty());
+
+    for s in &["a", "b", "c"] {
+        m1.insert(s.to_string());
+        m2.insert(s.to_string());
+        m3.insert(s.to_string());
+    }
+    m1.delete(&"b".to_string());
+    m2.delete(&"b".to_string());
+    m3.delete(&"b".to_string());
+
+    // len counts live items, not IDs handed out so far
+    assert_eq!(m1.len(), 2);
+    assert_eq!(m2.len(), 2);
+    assert_eq!(m3.len(), 2);
+    assert!(!m3.is_empty());
+}
+
+#[test]
+fn test_delete_missing() {
+    // Deleting something that isn't there is not an error, just false
+    // (and no warning on stderr: checking the result is up to the caller)
+    let mut m1 = IDManager1::new();
+    let mut m2 = IDManager2::new();
+    let mut m3 = IDManager3::new();
+    let mut m4 = IDManager4::new();
+    m1.insert("a".to_string());
+    m2.insert("a".to_string());
+    m3.insert("a".to_string());
+    m4.insert("a".to_string());
+
+    let missing = "b".to_string();
+    assert!(!m1.delete(&missing));
+    assert!(!m2.delete(&missing));
+    assert!(!m3.delete(&missing));
+    assert!(!m4.delete(&missing));
+    assert_eq!((m1.len(), m2.len(), m3.len(), m4.len()), (1, 1, 1, 1));
+}
+
+#[test]
+fn test_delete_missing_is_silent() {
+    // The test harness captures eprintln! output, so to see what the
+    // deletes above print, run that test again in a separate process
+    // with capturing turned off, and look at its stderr.
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "id_manager::test_delete_missing", "--nocapture"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1 passed"));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn test_contains() {
+    let mut m = IDManager3::new();
+    let id = m.insert("x".to_string());
+    assert!(m.contains_id(id));
+    assert!(m.contains_item(&"x".to_string()));
+    assert!(!m.contains_item(&"y".to_string()));
+
+    m.delete(&"x".to_string());
+    assert!(!m.contains_id(id));
+    assert!(!m.contains_item(&"x".to_string()));
+}
+
+#[test]
+fn test_clear() {
+    let mut m = IDManager3::new();
+    let old_id = m.insert("a".to_string());
+    m.insert("b".to_string());
+    m.clear();
+    assert!(m.id_to_item.is_empty());
+    assert!(m.item_to_id.is_empty());
+    assert_eq!(m.get_item(old_id), None);
+
+    // IDs keep counting after a clear
+    let new_id = m.insert("a".to_string());
+    assert_ne!(new_id, old_id);
+    assert_eq!(m.get_item(old_id), None);
+}
+
+#[test]
+fn test_iter() {
+    let mut m = IDManager3::new();
+    let words = vec!["a", "b", "c", "d"];
+    for w in &words {
+        m.insert(w.to_string());
+    }
+    let mut seen: Vec<&str> = Vec::new();
+    for (id, item) in m.iter() {
+        assert_eq!(m.get_id(item), Some(id));
+        seen.push(item);
+    }
+    seen.sort_unstable();
+    assert_eq!(seen, words);
+}
+
+#[test]
+fn test_items_ids() {
+    let mut m = IDManager3::new();
+    let a = m.insert("a");
+    let b = m.insert("b");
+    m.insert("c");
+    m.delete(&"c");
+    assert_eq!(m.items().count(), m.len());
+    let mut items: Vec<_> = m.items().copied().collect();
+    items.sort_unstable();
+    assert_eq!(items, vec!["a", "b"]);
+    let mut ids: Vec<_> = m.ids().collect();
+    ids.sort_by_key(ID::index);
+    assert_eq!(ids, vec![a, b]);
+}
+
+#[test]
+fn test_range() {
+    let mut m = IDManager3::new();
+    let ids = m.insert_many(0..10);
+    m.delete(&3);
+    m.delete(&5);
+    let found: Vec<_> = m.range(ids[2], ids[7]).collect();
+    assert_eq!(found, vec![(ids[2], &2), (ids[4], &4), (ids[6], &6)]);
+    assert_eq!(m.range(ids[3], ids[4]).count(), 0);
+    let (lo, hi) = (ID::default(), ID { index: 100, generation: 0 });
+    assert_eq!(m.range(lo, hi).count(), 8);
+    assert!(ids[0] < ids[1]);
+}
+
+#[test]
+fn test_into_iter() {
+    let mut m = IDManager3::new();
+    let a = m.insert("a".to_string());
+    let b = m.insert("b".to_string());
+
+    // If item_to_id were still alive, every try_unwrap would fail
+    // and this would come back empty.
+    let mut items: Vec<(ID, String)> = m.into_iter().collect();
+    items.sort_by_key(|(_, s)| s.clone());
+    assert_eq!(items, vec![(a, "a".to_string()), (b, "b".to_string())]);
+
+    // An item still shared elsewhere is skipped instead of panicking
+    let mut m = IDManager3::new();
+    let a = m.insert("a".to_string());
+    m.insert("b".to_string());
+    let extra = m.id_to_item[&a].clone();
+    let items: Vec<(ID, String)> = m.into_iter().collect();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].1, "b");
+    assert_eq!(*extra, "a");
+}
+
+#[test]
+fn test_delete_by_id() {
+    let mut m = IDManager3::new();
+    let id = m.insert("a".to_string());
+    assert_eq!(m.delete_by_id(id), Some("a".to_string()));
+    assert_eq!(m.get_item(id), None);
+    assert_eq!(m.get_id(&"a".to_string()), None);
+    assert_eq!(m.delete_by_id(id), None);
+}
+
+#[test]
+fn test_get_or_insert() {
+    let mut m = IDManager3::new();
+    let id1 = m.get_or_insert("a".to_string());
+    let id2 = m.get_or_insert("a".to_string());
+    assert_eq!(id1, id2);
+    assert_eq!(m.len(), 1);
+}
+
+#[test]
+fn test_with_capacity() {
+    let mut m1 = IDManager3::new();
+    let mut m2 = IDManager3::with_capacity(100);
+    m2.reserve(50);
+    for s in &["a", "b", "c"] {
+        assert_eq!(m1.insert(s.to_string()), m2.insert(s.to_string()));
+    }
+    assert_eq!(m1.len(), m2.len());
+    assert_eq!(m1.get_id(&"b".to_string()), m2.get_id(&"b".to_string()));
+}
+
+#[test]
+fn test_retain() {
+    let mut m = IDManager3::new();
+    for i in 0..10 {
+        m.insert(i);
+    }
+    m.retain(|_, &x| x % 2 == 0);
+    assert_eq!(m.len(), 5);
+    for i in 0..10 {
+        let id = m.get_id(&i);
+        assert_eq!(id.is_some(), i % 2 == 0);
+        if let Some(id) = id {
+            assert_eq!(m.get_item(id), Some(&i));
+        }
+    }
+    for (id, &x) in m.iter() {
+        assert_eq!(m.get_id(&x), Some(id));
+    }
+}
+
+#[test]
+fn test_recycle_ids() {
+    let mut m = IDManager3::new();
+    let a = m.insert("a".to_string());
+    let b = m.insert("b".to_string());
+    m.delete(&"a".to_string());
+    let c = m.insert("c".to_string());
+    assert_eq!(c.index, a.index);
+    assert_eq!(m.get_item(c), Some(&"c".to_string()));
+
+    // Free list is empty again, so back to stepping next_id
+    let d = m.insert("d".to_string());
+    assert_ne!(d.index, a.index);
+    assert_ne!(d.index, b.index);
+}
+
+#[test]
+fn test_stale_id_rejected() {
+    let mut m = IDManager3::new();
+    let old = m.insert("old".to_string());
+    m.delete(&"old".to_string());
+    let new = m.insert("new".to_string());
+
+    // Same slot, different generation
+    assert_eq!(new.index, old.index);
+    assert_ne!(new, old);
+    assert_eq!(m.get_item(old), None);
+    assert!(!m.contains_id(old));
+    assert_eq!(m.delete_by_id(old), None);
+    assert_eq!(m.get_item(new), Some(&"new".to_string()));
+}
+
+#[test]
+fn test_insert_many() {
+    let words = vec!["a", "b", "c"];
+    let mut m1 = IDManager3::new();
+    let ids = m1.insert_many(words.iter().map(|w| w.to_string()));
+
+    let mut m2 = IDManager3::new();
+    let expected: Vec<ID> =
+        words.iter().map(|w| m2.insert(w.to_string())).collect();
+    assert_eq!(ids, expected);
+    for (id, w) in ids.into_iter().zip(words) {
+        assert_eq!(m1.get_item(id), Some(&w.to_string()));
+    }
+}
+
+#[test]
+fn test_from_iter() {
+    let names = vec!["ann".to_string(), "bob".to_string(), "cy".to_string()];
+    let m: IDManager3<String> = names.clone().into_iter().collect();
+    assert_eq!(m.len(), names.len());
+    for name in &names {
+        assert!(m.contains_item(name));
+    }
+}
+
+#[test]
+fn test_ref_count() {
+    let mut m = IDManager3::new();
+    let id = m.insert("a".to_string());
+    assert_eq!(m.ref_count(id), Some(0));
+
+    let extra = m.id_to_item[&id].clone();
+    assert_eq!(m.ref_count(id), Some(1));
+    drop(extra);
+    assert_eq!(m.ref_count(id), Some(0));
+
+    m.delete_by_id(id);
+    assert_eq!(m.ref_count(id), None);
+
+    // After a duplicate insert, item_to_id only holds the first Rc
+    let first = m.insert("b".to_string());
+    let second = m.insert("b".to_string());
+    assert_eq!(m.ref_count(first), Some(0));
+    assert_eq!(m.ref_count(second), Some(0));
+}
+
+#[test]
+fn test_get_item_rc() {
+    let mut m = IDManager3::new();
+    let id = m.insert("a".to_string());
+    let item = m.get_item_rc(id).unwrap();
+    assert_eq!(m.ref_count(id), Some(1));
+
+    // Still shared, so the manager can't give the T itself back...
+    assert_eq!(m.delete_by_id(id), None);
+    assert_eq!(m.get_item(id), None);
+    // ...but our Rc keeps it alive
+    assert_eq!(*item, "a");
+    assert_eq!(Rc::strong_count(&item), 1);
+}
+
+#[test]
+fn test_debug() {
+    let mut m = IDManager3::new();
+    m.insert("hello".to_string());
+    let out = format!("{:?}", m);
+    assert!(out.starts_with("IDManager3 { next_id: "));
+    assert!(out.contains("\"hello\""));
+    // Each item is printed only once
+    assert_eq!(out.matches("hello").count(), 1);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    let mut m = IDManager3::new();
+    let a = m.insert("a".to_string());
+    m.insert("b".to_string());
+    let c = m.insert("c".to_string());
+    m.delete(&"b".to_string());
+
+    let json = serde_json::to_string(&m).unwrap();
+    let mut m2: IDManager3<String> = serde_json::from_str(&json).unwrap();
+    assert_eq!(m2.len(), 2);
+    for &(id, s) in &[(a, "a"), (c, "c")] {
+        assert_eq!(m2.get_item(id), Some(&s.to_string()));
+        assert_eq!(m2.get_id(&s.to_string()), Some(id));
+    }
+    // Fresh IDs don't collide with the restored ones
+    let d = m2.insert("d".to_string());
+    assert!(d != a && d != c);
+
+    // Two generations of one index can't both be live
+    let json = r#"{"next_id":{"index":2,"generation":0},"items":[
+        [{"index":0,"generation":0},"a"],
+        [{"index":0,"generation":1},"b"]]}"#;
+    assert!(serde_json::from_str::<IDManager3<String>>(json).is_err());
+}
+
+#[test]
+fn test_idmanager4_threads() {
+    use std::sync::Mutex;
+    use std::thread;
+
+    fn assert_send_sync<S: Send + Sync>() {}
+    assert_send_sync::<IDManager4<String>>();
+
+    let manager = Arc::new(Mutex::new(IDManager4::new()));
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let manager = Arc::clone(&manager);
+            thread::spawn(move || {
+                let id = manager.lock().unwrap().insert(format!("item{}", i));
+                // The Arc outlives the lock guard
+                let item = manager.lock().unwrap().get_item(id).unwrap();
+                assert_eq!(*item, format!("item{}", i));
+                id
+            })
+        })
+        .collect();
+    let ids: Vec<ID> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    let manager = manager.lock().unwrap();
+    assert_eq!(manager.len(), 4);
+    for id in ids {
+        let item = manager.get_item(id).unwrap();
+        assert_eq!(manager.get_id(&item), Some(id));
+    }
+}
+
+#[test]
+fn test_try_insert_exhausted() {
+    let mut m = IDManager3::new();
+    m.next_id.index = usize::MAX - 1;
+    let last = m.try_insert("a".to_string()).unwrap();
+    assert_eq!(last.index, usize::MAX - 1);
+    assert_eq!(m.try_insert("b".to_string()), Err(IdExhausted));
+    assert_eq!(m.len(), 1);
+    assert!(!m.contains_item(&"b".to_string()));
+
+    // A recycled ID still works when next_id is used up
+    m.delete(&"a".to_string());
+    assert!(m.try_insert("b".to_string()).is_ok());
+}
+
+#[test]
+fn test_drain() {
+    let mut m: IDManager3<String> =
+        vec!["a".to_string(), "b".to_string()].into_iter().collect();
+    let mut drained: Vec<String> = m.drain().map(|(_, s)| s).collect();
+    drained.sort();
+    assert_eq!(drained, vec!["a", "b"]);
+    assert!(m.is_empty());
+    assert!(m.item_to_id.is_empty());
+
+    // Still usable afterwards
+    let id = m.insert("c".to_string());
+    assert_eq!(m.get_item(id), Some(&"c".to_string()));
+}
+
+#[test]
+fn test_check_invariant() {
+    // Duplicate inserts break the mirror, and the check notices
+    let mut m = IDManager3::new();
+    m.insert("a".to_string());
+    assert!(m.check_invariant());
+    m.insert("a".to_string());
+    assert!(!m.check_invariant());
+}
+
+#[test]
+fn test_invariant_random_ops() {
+    // Tiny linear congruential generator, so the test is reproducible
+    // without pulling in a random number crate
+    let mut seed: u64 = 198;
+    let mut next = move || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (seed >> 33) as usize
+    };
+
+    let mut m2 = IDManager2::new();
+    let mut m3 = IDManager3::new();
+    for _ in 0..2000 {
+        let x = next() % 50;
+        // toggle x: delete it if present, insert it if not
+        if m3.contains_item(&x) {
+            assert!(m2.delete(&x));
+            assert!(m3.delete(&x));
+        } else {
+            m2.insert(x);
+            m3.insert(x);
+        }
+        assert!(m2.check_invariant());
+        assert!(m3.check_invariant());
+        assert_eq!(m2.len(), m3.len());
+    }
+}
+
+#[test]
+fn test_idmanager2_survives_rehash() {
+    let mut m = IDManager2::new();
+    let first = m.insert("item0".to_string());
+    // Plenty of inserts to force the maps to grow several times
+    for i in 1..10_000 {
+        m.insert(format!("item{}", i));
+    }
+    assert_eq!(m.get_id(&"item0".to_string()), Some(first));
+    for i in 0..100 {
+        let id = m.get_id(&format!("item{}", i)).unwrap();
+        assert_eq!(m.get_item(id), Some(&format!("item{}", i)));
+    }
+    assert!(m.check_invariant());
+}
+
+#[test]
+fn test_merge() {
+    let mut m1 = IDManager3::new();
+    let a1 = m1.insert("a".to_string());
+    let b1 = m1.insert("b".to_string());
+
+    let mut m2 = IDManager3::new();
+    let b2 = m2.insert("b".to_string());
+    let c2 = m2.insert("c".to_string());
+
+    let remap = m1.merge(m2);
+    assert_eq!(remap.len(), 2);
+    // Shared item keeps m1's ID
+    assert_eq!(remap[&b2], b1);
+    // New item gets a fresh one
+    let c1 = remap[&c2];
+    assert_ne!(c1, a1);
+    assert_ne!(c1, b1);
+    assert_eq!(m1.get_item(c1), Some(&"c".to_string()));
+    assert_eq!(m1.len(), 3);
+    assert!(m1.check_invariant());
+}
+
+#[test]
+fn test_entry() {
+    let mut m = IDManager3::new();
+    let a = m.insert("a".to_string());
+
+    match m.entry("a".to_string()) {
+        Entry::Occupied(id) => assert_eq!(id, a),
+        Entry::Vacant(_) => panic!("a should be occupied"),
+    }
+
+    let b = match m.entry("b".to_string()) {
+        Entry::Occupied(_) => panic!("b should be vacant"),
+        Entry::Vacant(entry) => {
+            assert_eq!(entry.item(), "b");
+            entry.insert()
+        }
+    };
+    assert_eq!(m.get_item(b), Some(&"b".to_string()));
+    assert_eq!(m.entry("b".to_string()).or_insert(), b);
+    assert_eq!(m.len(), 2);
+}
+
+#[test]
+fn test_id_usize_round_trip() {
+    let mut m = IDManager3::new();
+    let a = m.insert("a".to_string());
+    let b = m.insert("b".to_string());
+    let round_trip = |id: ID| ID::from_usize(id.as_usize().unwrap()).unwrap();
+    assert_eq!(round_trip(a), a);
+    assert_eq!(round_trip(b), b);
+    assert_eq!(m.get_item(round_trip(b)), Some(&"b".to_string()));
+    // Made up IDs just aren't found
+    assert_eq!(m.get_item(ID::from_usize(12345).unwrap()), None);
+
+    // A recycled ID keeps its generation, so it doesn't come back as the
+    // stale first-generation one
+    m.delete(&"a".to_string());
+    let c = m.insert("c".to_string());
+    assert_eq!(c.index(), a.index());
+    assert_eq!(round_trip(c), c);
+    assert_ne!(c.as_usize(), a.as_usize());
+    assert_eq!(m.get_item(round_trip(c)), Some(&"c".to_string()));
+    assert_eq!(m.get_item(round_trip(a)), None);
+
+    // Big indices still fit in the u128 form, but not in a usize
+    let big = ID { index: 1 << 40, generation: 3 };
+    assert_eq!(ID::from_bits(big.to_bits()), Some(big));
+    if usize::BITS == 64 {
+        assert_eq!(big.as_usize(), None);
+    }
+    let last = ID { index: usize::MAX, generation: u32::MAX };
+    assert_eq!(ID::from_bits(last.to_bits()), Some(last));
+    assert_eq!(ID::from_bits(u128::MAX), None);
+}
+
+#[test]
+fn test_id_display() {
+    let id = ID { index: 42, generation: 0 };
+    assert_eq!(id.to_string(), "#42");
+    assert_eq!(id.next_generation().to_string(), "#42@1");
+    assert_eq!(format!("{:?}", id), "ID { index: 42, generation: 0 }");
+}
+
+#[test]
+fn test_compact() {
+    let mut m = IDManager3::new();
+    let ids = m.insert_many(vec!["a", "b", "c", "d", "e"]);
+    m.delete(&"b");
+    m.delete(&"d");
+    let remap = m.compact();
+
+    assert_eq!(remap.len(), 3);
+    let mut new_ids: Vec<usize> = m.iter().map(|(id, _)| id.index()).collect();
+    new_ids.sort_unstable();
+    assert_eq!(new_ids, vec![0, 1, 2]);
+    for (old_id, item) in [(ids[0], "a"), (ids[2], "c"), (ids[4], "e")] {
+        assert_eq!(m.get_item(remap[&old_id]), Some(&item));
+    }
+    assert!(!remap.contains_key(&ids[1]));
+    assert!(m.check_invariant());
+
+    // No free list left: the next item goes right after the others
+    assert_eq!(m.insert("f").index(), 3);
+}
+
+#[test]
+fn test_compact_stale_ids() {
+    let mut m = IDManager3::new();
+    let a = m.insert("a");
+    let b = m.insert("b");
+    m.delete(&"b");
+    let remap = m.compact();
+    assert_eq!(m.get_item(remap[&a]), Some(&"a"));
+
+    // "c" lands on b's old index, but b must not find it
+    let c = m.insert("c");
+    assert_eq!(c.index, b.index);
+    assert_eq!(m.get_item(b), None);
+    assert_eq!(m.get_item(c), Some(&"c"));
+
+    // Same for IDs thrown away by a clear before compacting
+    let old = m.get_id(&"a").unwrap();
+    m.clear();
+    m.insert("d");
+    m.compact();
+    assert_eq!(m.get_id(&"d").unwrap().index, old.index);
+    assert_eq!(m.get_item(old), None);
+}
+
+#[test]
+fn test_partial_eq_by_items() {
+    let mut a = IDManager3::new();
+    a.insert_many(vec!["x", "y", "z"]);
+    let mut b = IDManager3::new();
+    b.insert_many(vec!["z", "x", "y"]);
+    assert_ne!(a.get_id(&"x"), b.get_id(&"x"));
+    assert_eq!(a, b);
+
+    b.delete(&"y");
+    assert_ne!(a, b);
+    b.insert("w");
+    assert_ne!(a, b);
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut m = IDManager3::new();
+    let a = m.insert("a".to_string());
+    let b = m.insert("b".to_string());
+    let snap = m.snapshot();
+
+    m.delete(&"a".to_string());
+    m.insert("c".to_string());
+    // Shared with the snapshot, so it can't be mutated in place...
+    assert!(m.get_item_mut(b).is_none());
+    // ...but it can be deleted (the snapshot keeps it alive)
+    assert_eq!(m.delete_by_id(b), None);
+    assert_eq!(m.get_item(b), None);
+
+    m.restore(snap);
+    assert_eq!(m.len(), 2);
+    assert_eq!(m.get_item(a), Some(&"a".to_string()));
+    assert_eq!(m.get_item(b), Some(&"b".to_string()));
+    assert_eq!(m.get_id(&"c".to_string()), None);
+    assert!(m.check_invariant());
+    // The ID counter went back too
+    assert_eq!(m.insert("c".to_string()).index(), 2);
+}
+
+#[test]
+fn test_insert_with_id() {
+    let mut m = IDManager3::new();
+    let hundred = ID { index: 100, generation: 0 };
+    assert_eq!(m.insert_with_id(hundred, "x"), Ok(()));
+    assert_eq!(m.get_item(hundred), Some(&"x"));
+    assert!(m.insert("y") > hundred);
+
+    assert_eq!(
+        m.insert_with_id(hundred, "z"),
+        Err(InsertError::Occupied(hundred))
+    );
+    assert_eq!(
+        m.insert_with_id(ID { index: 7, generation: 0 }, "x"),
+        Err(InsertError::DuplicateItem(hundred))
+    );
+
+    // A freed index that gets imported is not handed out again
+    let a = m.insert("a");
+    m.delete(&"a");
+    m.insert_with_id(a, "imported").unwrap();
+    let b = m.insert("b");
+    assert_ne!(b.index(), a.index());
+    assert!(m.check_invariant());
+}
+
+#[test]
+fn test_custom_hasher() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+
+    // Fixed (unseeded) hasher: same hashes on every run
+    type Fixed = BuildHasherDefault<DefaultHasher>;
+    let mut m: IDManager3<String, Fixed> = IDManager3::default();
+    let a = m.insert("a".to_string());
+    let b = m.get_or_insert("b".to_string());
+    assert_eq!(m.get_id(&"a".to_string()), Some(a));
+    assert_eq!(m.get_item(b), Some(&"b".to_string()));
+    m.get_item_mut(b).unwrap().push('!');
+    assert_eq!(m.get_id(&"b!".to_string()), Some(b));
+
+    let collected: IDManager3<String, Fixed> =
+        m.into_iter().map(|(_, s)| s).collect();
+    assert_eq!(collected.len(), 2);
+}
+
+/*
+    ========== THREAD-SAFE VARIANT ==========
+
+    Rc<T> is not thread-safe: its reference count is updated with plain
+    (non-atomic) arithmetic, so IDManager3 is neither Send nor Sync.
+
+    Swapping Rc for Arc ("atomically reference counted") is all it
+    takes. Nothing else changes, and the whole structure is
+    Send + Sync whenever T is. Put it behind a Mutex to share it
+    between threads.
+
+    get_item hands back a clone of the Arc<T>, so callers can carry the
+    item off to another thread without holding the lock.
+*/
+
+use std::sync::Arc;
+
+pub struct IDManager4<T>
+where
+    T: Eq + Hash,
+{
+    next_id: ID,
+    id_to_item: HashMap<ID, Arc<T>>,
+    item_to_id: HashMap<Arc<T>, ID>,
+}
+
+impl<T> Default for IDManager4<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        // Empty maps
+        Self {
+            next_id: Default::default(),
+            id_to_item: Default::default(),
+            item_to_id: Default::default(),
+        }
+    }
+}
+
+impl<T> IDManager4<T>
+where
+    T: Eq + Hash,
+{
+    pub fn new() -> Self {
+        // Doesn't store any IDs
+        Default::default()
+    }
+
+    // The bidirectional map
+    pub fn get_id(&self, item: &T) -> Option<ID> {
+        self.item_to_id.get(item).copied()
+    }
+    pub fn get_item(&self, id: ID) -> Option<Arc<T>> {
+        self.id_to_item.get(&id).cloned()
+    }
+
+    // Number of items currently stored (not the number ever assigned)
+    pub fn len(&self) -> usize {
+        self.id_to_item.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.id_to_item.is_empty()
+    }
+
+    // Insertion and deletion
+    pub fn insert(&mut self, item: T) -> ID {
+        let id = self.next_id;
+        let item_ref = Arc::new(item);
+        self.id_to_item.insert(id, item_ref.clone());
+        self.item_to_id.insert(item_ref, id);
+        self.next_id.step();
+        id
+    }
+    pub fn delete(&mut self, item: &T) -> bool {
+        // true if item existed, false if not
+        if let Some(id) = self.get_id(item) {
+            self.id_to_item.remove(&id);
+            self.item_to_id.remove(item);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[test]
+fn test_get_item_mut() {
+    let mut m = IDManager3::new();
+    let id = m.insert("a".to_string());
+    m.get_item_mut(id).unwrap().push('b');
+
+    // Re-filed under its new hash
+    assert_eq!(m.get_item(id), Some(&"ab".to_string()));
+    assert_eq!(m.get_id(&"ab".to_string()), Some(id));
+    assert_eq!(m.get_id(&"a".to_string()), None);
+
+    // Refused while the item is aliased
+    let extra = m.id_to_item[&id].clone();
+    assert!(m.get_item_mut(id).is_none());
+    assert_eq!(m.get_id(&"ab".to_string()), Some(id));
+    drop(extra);
+    assert!(m.get_item_mut(id).is_some());
+}
+
+/*
+    ========== GRAPH ==========
+
+    A common use of an ID manager: the vertex table of a graph. Edges
+    refer to vertices by ID, so the vertices themselves can be anything
+    Eq + Hash, and need not be Clone.
+
+    Edges are directed, stored as adjacency lists. When a vertex goes,
+    so do its outgoing edges and every edge pointing at it; otherwise
+    neighbors() would keep reporting a vertex that no longer exists.
+*/
+
+pub struct Graph<T>
+where
+    T: Eq + Hash,
+{
+    vertices: IDManager3<T>,
+    edges: HashMap<ID, Vec<ID>>,
+}
+
+impl<T> Default for Graph<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        Self { vertices: Default::default(), edges: Default::default() }
+    }
+}
+
+impl<T> Graph<T>
+where
+    T: Eq + Hash,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn vertices(&self) -> &IDManager3<T> {
+        &self.vertices
+    }
+    pub fn add_vertex(&mut self, item: T) -> ID {
+        self.vertices.insert(item)
+    }
+    // Removes the vertex and every edge touching it.
+    // Like IDManager3::delete_by_id, returns None if the vertex is still
+    // shared through get_item_rc -- but it is removed (edges and all)
+    // either way, so the edges are cleaned up before trying to unwrap.
+    pub fn remove_vertex(&mut self, id: ID) -> Option<T> {
+        if !self.vertices.contains_id(id) {
+            return None;
+        }
+        self.edges.remove(&id);
+        for targets in self.edges.values_mut() {
+            targets.retain(|&target| target != id);
+        }
+        self.vertices.delete_by_id(id)
+    }
+
+    // Edge from a to b. Returns false (and adds nothing) if either vertex
+    // doesn't exist. Adding the same edge twice has no effect.
+    pub fn add_edge(&mut self, a: ID, b: ID) -> bool {
+        if !self.vertices.contains_id(a) || !self.vertices.contains_id(b) {
+            return false;
+        }
+        let targets = self.edges.entry(a).or_default();
+        if !targets.contains(&b) {
+            targets.push(b);
+        }
+        true
+    }
+    // Where the edges from id lead, in the order they were added
+    pub fn neighbors(&self, id: ID) -> &[ID] {
+        self.edges.get(&id).map_or(&[], |targets| targets.as_slice())
+    }
+}
+
+#[test]
+fn test_graph() {
+    let mut g = Graph::new();
+    let a = g.add_vertex("a");
+    let b = g.add_vertex("b");
+    let c = g.add_vertex("c");
+    assert!(g.add_edge(a, b));
+    assert!(g.add_edge(a, c));
+    assert!(g.add_edge(c, b));
+    assert!(g.add_edge(a, b));
+    assert_eq!(g.neighbors(a), &[b, c]);
+    assert_eq!(g.neighbors(b), &[]);
+
+    // Deleting b takes every edge into b with it
+    assert_eq!(g.remove_vertex(b), Some("b"));
+    assert_eq!(g.neighbors(a), &[c]);
+    assert_eq!(g.neighbors(c), &[]);
+    assert!(!g.add_edge(a, b));
+    assert_eq!(g.vertices().len(), 2);
+
+    // ...and every edge out of c
+    g.add_edge(c, a);
+    g.remove_vertex(c);
+    assert_eq!(g.neighbors(a), &[]);
+    assert_eq!(g.neighbors(c), &[]);
+}
+
+#[test]
+fn test_graph_remove_shared_vertex() {
+    let mut g = Graph::new();
+    let a = g.add_vertex("a");
+    let b = g.add_vertex("b");
+    g.add_edge(a, b);
+    g.add_edge(b, a);
+
+    // Can't hand the item back while it's shared, but still removed
+    let held = g.vertices().get_item_rc(b).unwrap();
+    assert_eq!(g.remove_vertex(b), None);
+    assert!(!g.vertices().contains_id(b));
+    assert_eq!(g.neighbors(a), &[]);
+    assert_eq!(g.neighbors(b), &[]);
+    assert_eq!(*held, "b");
+}
+
+/*
+    ========== INSERTION ORDER ==========
+
+    IDManager3::iter goes in HashMap order, which changes from run to run.
+    For reproducible output, this wrapper also records the IDs in the order
+    they were handed out. (Recycled IDs go to the back: it is the order of
+    insertion, not of ID.)
+*/
+
+pub struct OrderedIDManager<T>
+where
+    T: Eq + Hash,
+{
+    manager: IDManager3<T>,
+    order: Vec<ID>,
+}
+
+impl<T> Default for OrderedIDManager<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        Self { manager: Default::default(), order: Default::default() }
+    }
+}
+
+impl<T> OrderedIDManager<T>
+where
+    T: Eq + Hash,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get_id(&self, item: &T) -> Option<ID> {
+        self.manager.get_id(item)
+    }
+    pub fn get_item(&self, id: ID) -> Option<&T> {
+        self.manager.get_item(id)
+    }
+    pub fn len(&self) -> usize {
+        self.manager.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.manager.is_empty()
+    }
+
+    pub fn insert(&mut self, item: T) -> ID {
+        let id = self.manager.insert(item);
+        self.order.push(id);
+        id
+    }
+    pub fn delete(&mut self, item: &T) -> bool {
+        match self.get_id(item) {
+            Some(id) => self.delete_by_id(id).is_some(),
+            None => false,
+        }
+    }
+    pub fn delete_by_id(&mut self, id: ID) -> Option<T> {
+        let item = self.manager.delete_by_id(id)?;
+        self.order.retain(|&x| x != id);
+        Some(item)
+    }
+
+    // Oldest first
+    pub fn iter_ordered(&self) -> impl Iterator<Item = (ID, &T)> {
+        self.order
+            .iter()
+            .filter_map(move |&id| Some((id, self.manager.get_item(id)?)))
+    }
+}
+
+#[test]
+fn test_ordered_id_manager() {
+    let mut m = OrderedIDManager::new();
+    let a = m.insert("a");
+    m.insert("b");
+    let c = m.insert("c");
+    assert!(m.delete(&"b"));
+    assert!(!m.delete(&"b"));
+    let items: Vec<_> = m.iter_ordered().collect();
+    assert_eq!(items, vec![(a, &"a"), (c, &"c")]);
+
+    // d reuses b's index, but still comes last
+    let d = m.insert("d");
+    let items: Vec<_> = m.iter_ordered().map(|(_, item)| *item).collect();
+    assert_eq!(items, vec!["a", "c", "d"]);
+    assert_eq!(m.delete_by_id(a), Some("a"));
+    assert_eq!(m.iter_ordered().next(), Some((c, &"c")));
+    assert_eq!(m.len(), 2);
+    assert_eq!(m.get_item(d), Some(&"d"));
+}
+
+/*
+    ========== NAMESPACES ==========
+
+    IDs scoped by category ("user", "post", ...): each namespace is an
+    independent IDManager3 with its own counter, so the same ID means
+    different things in different namespaces. A namespace is created the
+    first time something is inserted into it.
+*/
+
+pub struct NamespacedIDManager<T>
+where
+    T: Eq + Hash,
+{
+    namespaces: HashMap<String, IDManager3<T>>,
+}
+
+impl<T> Default for NamespacedIDManager<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        Self { namespaces: Default::default() }
+    }
+}
+
+impl<T> NamespacedIDManager<T>
+where
+    T: Eq + Hash,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // The manager for one namespace, if anything was ever inserted into it
+    pub fn namespace(&self, ns: &str) -> Option<&IDManager3<T>> {
+        self.namespaces.get(ns)
+    }
+    pub fn get_id(&self, ns: &str, item: &T) -> Option<ID> {
+        self.namespaces.get(ns)?.get_id(item)
+    }
+    pub fn get_item(&self, ns: &str, id: ID) -> Option<&T> {
+        self.namespaces.get(ns)?.get_item(id)
+    }
+    // Total over all namespaces
+    pub fn len(&self) -> usize {
+        self.namespaces.values().map(IDManager3::len).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&mut self, ns: &str, item: T) -> ID {
+        // get_mut first, so that the String is only allocated once per
+        // namespace rather than on every insert
+        match self.namespaces.get_mut(ns) {
+            Some(manager) => manager.insert(item),
+            None => {
+                self.namespaces.entry(ns.to_string()).or_default().insert(item)
+            }
+        }
+    }
+    pub fn delete_by_id(&mut self, ns: &str, id: ID) -> Option<T> {
+        self.namespaces.get_mut(ns)?.delete_by_id(id)
+    }
+}
+
+#[test]
+fn test_namespaced_id_manager() {
+    let mut m = NamespacedIDManager::new();
+    let alice = m.insert("user", "alice".to_string());
+    let post = m.insert("post", "hello world".to_string());
+    // Separate counters: both got the first ID
+    assert_eq!(alice, post);
+    assert_eq!(m.get_item("user", alice), Some(&"alice".to_string()));
+    assert_eq!(m.get_item("post", alice), Some(&"hello world".to_string()));
+    assert_eq!(m.get_item("comment", alice), None);
+
+    let bob = m.insert("user", "bob".to_string());
+    assert_eq!(m.get_id("user", &"bob".to_string()), Some(bob));
+    assert_eq!(m.get_id("post", &"bob".to_string()), None);
+    assert_eq!(m.len(), 3);
+    assert_eq!(m.namespace("user").unwrap().len(), 2);
+
+    assert_eq!(m.delete_by_id("post", post), Some("hello world".to_string()));
+    assert_eq!(m.get_item("user", alice), Some(&"alice".to_string()));
+    assert_eq!(m.len(), 2);
+}
+
+/*
+    ========== ONE-WAY VARIANT ==========
+
+    The item -> ID direction is what forces T: Eq + Hash on the other
+    managers. Plenty of types can't have that (anything with an f64 in
+    it, for one), and often ID -> item is all that is needed.
+    Dropping the reverse map also drops the need for Rc: each item has a
+    single owner again, so handing out &mut T is no problem either.
+*/
+
+pub struct OneWayIDManager<T> {
+    next_id: ID,
+    id_to_item: HashMap<ID, T>,
+}
+
+impl<T> Default for OneWayIDManager<T> {
+    fn default() -> Self {
+        Self { next_id: Default::default(), id_to_item: Default::default() }
+    }
+}
+
+impl<T> OneWayIDManager<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get_item(&self, id: ID) -> Option<&T> {
+        self.id_to_item.get(&id)
+    }
+    pub fn get_item_mut(&mut self, id: ID) -> Option<&mut T> {
+        self.id_to_item.get_mut(&id)
+    }
+    pub fn len(&self) -> usize {
+        self.id_to_item.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.id_to_item.is_empty()
+    }
+
+    // Every insert gets a new ID, even for an item equal to an earlier one
+    // (there is no way to tell!)
+    pub fn insert(&mut self, item: T) -> ID {
+        let id = self.next_id;
+        self.id_to_item.insert(id, item);
+        self.next_id.step();
+        id
+    }
+    pub fn delete_by_id(&mut self, id: ID) -> Option<T> {
+        self.id_to_item.remove(&id)
+    }
+}
+
+#[test]
+fn test_one_way_id_manager() {
+    // Neither Eq nor Hash
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    let mut m = OneWayIDManager::new();
+    let p = m.insert(Point { x: 1.0, y: 2.5 });
+    let q = m.insert(Point { x: 1.0, y: 2.5 });
+    assert_ne!(p, q);
+    m.get_item_mut(p).unwrap().x = -1.0;
+    assert_eq!(m.get_item(p), Some(&Point { x: -1.0, y: 2.5 }));
+    assert_eq!(m.delete_by_id(q), Some(Point { x: 1.0, y: 2.5 }));
+    assert_eq!(m.get_item(q), None);
+    assert_eq!(m.len(), 1);
+}
+
+/*
+    ========== SLOT MAP ==========
+
+    If IDs are handed out by us anyway, why hash them at all? Make the
+    index an actual index into a Vec. Every operation is then O(1) with no
+    hashing (of IDs or items), and the items sit next to each other in
+    memory.
+
+    Removing an item leaves an empty slot, recorded on a free list and
+    reused by the next insert. Each slot also keeps the generation of
+    its current occupant, exactly like IDManager3's IDs, so a stale ID
+    doesn't find the next occupant of its slot.
+*/
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+pub struct SlotMap<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl<T> Default for SlotMap<T> {
+    fn default() -> Self {
+        Self { slots: Vec::new(), free: Vec::new(), len: 0 }
+    }
+}
+
+impl<T> SlotMap<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, value: T) -> ID {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index];
+            slot.value = Some(value);
+            return ID { index, generation: slot.generation };
+        }
+        self.slots.push(Slot { generation: 0, value: Some(value) });
+        ID { index: self.slots.len() - 1, generation: 0 }
+    }
+    pub fn get(&self, id: ID) -> Option<&T> {
+        let slot = self.slots.get(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+    pub fn get_mut(&mut self, id: ID) -> Option<&mut T> {
+        let slot = self.slots.get_mut(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+    pub fn contains(&self, id: ID) -> bool {
+        self.get(id).is_some()
+    }
+    pub fn remove(&mut self, id: ID) -> Option<T> {
+        let slot = self.slots.get_mut(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        // The next occupant gets a new generation
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        self.len -= 1;
+        Some(value)
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[test]
+fn test_slot_map() {
+    let mut m = SlotMap::new();
+    let ids: Vec<ID> = (0..1000).map(|i| m.insert(i * 10)).collect();
+    // Dense: the IDs are exactly the Vec indices
+    for (i, id) in ids.iter().enumerate() {
+        assert_eq!(id.index(), i);
+        assert_eq!(m.get(*id), Some(&(i * 10)));
+    }
+
+    assert_eq!(m.remove(ids[500]), Some(5000));
+    assert_eq!(m.remove(ids[500]), None);
+    assert_eq!(m.len(), 999);
+    // The slot is reused, but the old ID stays dead
+    let new = m.insert(42);
+    assert_eq!(new.index(), 500);
+    assert_ne!(new, ids[500]);
+    assert_eq!(m.get(ids[500]), None);
+    assert_eq!(m.get(new), Some(&42));
+
+    *m.get_mut(ids[0]).unwrap() += 1;
+    assert_eq!(m.get(ids[0]), Some(&1));
+    assert!(!m.contains(ID { index: 5000, generation: 0 }));
+}
+
+/*
+    ========== ARENA VARIANT ==========
+
+    IDManager2 went wrong because the items lived in a HashMap, which
+    moves them when it grows, leaving the raw pointers dangling.
+    Store the items in an Arena (see mem.rs) instead: an arena never moves
+    or frees anything before it is dropped itself. So pointers into it
+    stay valid as long as the manager does, and get_item can return a
+    plain &T, even while more items are being inserted.
+
+    That is also why insert only takes &self (the maps are in RefCells):
+    with &mut self, the borrow checker wouldn't let anyone hold on to a &T
+    across an insert, even though it is perfectly safe here.
+
+    Deleting only takes the item out of the maps. Its memory stays in the
+    arena until the manager is dropped, so references to it remain valid
+    (that's the price: deleted items are not freed early).
+*/
+
+use crate::mem::Arena;
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+
+// A pointer into the arena, which hashes and compares as the item itself
+// (and can be looked up by &T, thanks to Borrow).
+// Safety: only ever created from a reference into the manager's arena,
+// so always valid to dereference while the manager is alive.
+struct ItemRef<T>(*const T);
+
+impl<T> ItemRef<T> {
+    fn get(&self) -> &T {
+        unsafe { &*self.0 }
+    }
+}
+impl<T: Hash> Hash for ItemRef<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.get().hash(state)
+    }
+}
+impl<T: PartialEq> PartialEq for ItemRef<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+impl<T: Eq> Eq for ItemRef<T> {}
+impl<T> Borrow<T> for ItemRef<T> {
+    fn borrow(&self) -> &T {
+        self.get()
+    }
+}
+
+pub struct ArenaIDManager<T>
+where
+    T: Eq + Hash,
+{
+    next_id: Cell<ID>,
+    id_to_item: RefCell<HashMap<ID, ItemRef<T>>>,
+    item_to_id: RefCell<HashMap<ItemRef<T>, ID>>,
+    // Declared last, so it is dropped after the maps pointing into it
+    arena: Arena<T>,
+}
+
+impl<T> Default for ArenaIDManager<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        Self {
+            next_id: Default::default(),
+            id_to_item: Default::default(),
+            item_to_id: Default::default(),
+            arena: Arena::new(),
+        }
+    }
+}
+
+impl<T> ArenaIDManager<T>
+where
+    T: Eq + Hash,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get_id(&self, item: &T) -> Option<ID> {
+        self.item_to_id.borrow().get(item).copied()
+    }
+    // The &T lives as long as the manager, not just as long as a borrow
+    // of one of the RefCells
+    pub fn get_item(&self, id: ID) -> Option<&T> {
+        let ptr = self.id_to_item.borrow().get(&id)?.0;
+        // Safety: see ItemRef; the arena is part of self
+        Some(unsafe { &*ptr })
+    }
+    pub fn len(&self) -> usize {
+        self.id_to_item.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.id_to_item.borrow().is_empty()
+    }
+
+    // An item that is already stored keeps its ID (nothing new is
+    // allocated for it)
+    pub fn insert(&self, item: T) -> ID {
+        if let Some(id) = self.get_id(&item) {
+            return id;
+        }
+        let id = self.next_id.get();
+        let item: *const T = self.arena.alloc(item);
+        self.id_to_item.borrow_mut().insert(id, ItemRef(item));
+        self.item_to_id.borrow_mut().insert(ItemRef(item), id);
+        let mut next_id = id;
+        next_id.step();
+        self.next_id.set(next_id);
+        id
+    }
+    pub fn delete(&self, item: &T) -> bool {
+        match self.item_to_id.borrow_mut().remove(item) {
+            Some(id) => {
+                self.id_to_item.borrow_mut().remove(&id);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[test]
+fn test_arena_id_manager() {
+    let m = ArenaIDManager::new();
+    let first_id = m.insert("first".to_string());
+    let first: &String = m.get_item(first_id).unwrap();
+
+    // Plenty of inserts: both maps rehash and the arena grows
+    for i in 0..1000 {
+        m.insert(i.to_string());
+    }
+    assert_eq!(first, "first");
+    assert_eq!(m.get_id(&"first".to_string()), Some(first_id));
+    assert_eq!(
+        m.get_item(m.get_id(&"500".to_string()).unwrap()),
+        Some(&"500".to_string())
+    );
+    assert_eq!(m.insert("first".to_string()), first_id);
+    assert_eq!(m.len(), 1001);
+
+    // Deleted, but not freed: the old reference still works
+    assert!(m.delete(&"first".to_string()));
+    assert!(!m.delete(&"first".to_string()));
+    assert_eq!(m.get_item(first_id), None);
+    assert_eq!(first, "first");
+    assert_eq!(m.len(), 1000);
 }
 
 /*
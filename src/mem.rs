@@ -47,40 +47,388 @@ impl Drop for Person {
     }
 }
 
+// A Drop impl that does something observable: record a label in a shared
+// log when dropped. Handy for checking in which order things get dropped,
+// which is not always obvious.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct DropLogger {
+    label: String,
+    log: Rc<RefCell<Vec<String>>>,
+}
+impl DropLogger {
+    pub fn new(label: &str, log: &Rc<RefCell<Vec<String>>>) -> Self {
+        Self { label: label.to_string(), log: Rc::clone(log) }
+    }
+}
+impl Drop for DropLogger {
+    fn drop(&mut self) {
+        self.log.borrow_mut().push(self.label.clone());
+    }
+}
+
+#[test]
+fn test_drop_order() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    // Fields of a tuple (or struct) are dropped in declaration order
+    let pair =
+        (DropLogger::new("first", &log), DropLogger::new("second", &log));
+    drop(pair);
+    assert_eq!(*log.borrow(), vec!["first", "second"]);
+    log.borrow_mut().clear();
+
+    // Local variables are dropped in reverse order of declaration
+    {
+        let _a = DropLogger::new("a", &log);
+        let _b = DropLogger::new("b", &log);
+    }
+    assert_eq!(*log.borrow(), vec!["b", "a"]);
+}
+
 // Perhaps a more practical example:
 // Here is a weird struct for making a dynamically allocated string that lives
 // permanently
 // This can also be done without unsafe pointers,
 // e.g. with the function Box::leak, the below is just for illustration
 
+use std::cell::Cell;
+use std::mem::ManuallyDrop;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// How many permanent references have been handed out, across the whole
+// process. Each one pins its string's contents forever; leaking is on
+// purpose, but it is nice to be able to check that it doesn't happen more
+// than expected. (Asking the same string twice counts twice, even though
+// its contents only leak once.)
+static LEAKED_PERMANENT: AtomicUsize = AtomicUsize::new(0);
 
+pub fn leaked_permanent_count() -> usize {
+    LEAKED_PERMANENT.load(Ordering::SeqCst)
+}
+
+// Tests run in parallel, so the ones that hand out permanent references
+// take this lock to keep the global count predictable.
+#[cfg(test)]
+static LEAK_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// Careful: an empty drop() is not enough to leak a field! The fields are
+// still dropped right after drop() returns. To actually skip dropping
+// contents, it is wrapped in ManuallyDrop, and only dropped by hand.
 pub struct PermanentString {
-    contents: Box<String>,
+    contents: ManuallyDrop<Box<String>>,
     contents_ptr: *const str,
+    gave_permanent: Cell<bool>,
 }
 impl PermanentString {
     pub fn new(s: &str) -> Self {
         let contents = Box::new(s.to_string());
         let contents_ptr: *const str = contents.as_ref().deref();
-        Self { contents, contents_ptr }
+        Self {
+            contents: ManuallyDrop::new(contents),
+            contents_ptr,
+            gave_permanent: Cell::new(false),
+        }
     }
     pub fn get_temporary_reference(&self) -> &str {
         self.contents.as_ref()
     }
     pub fn get_permanent_reference(&self) -> &'static str {
+        // Remember that the contents must now outlive self
+        self.gave_permanent.set(true);
+        LEAKED_PERMANENT.fetch_add(1, Ordering::SeqCst);
         unsafe { self.contents_ptr.as_ref().unwrap() }
     }
+    // Whether dropping self will leak the contents
+    pub fn will_leak(&self) -> bool {
+        self.gave_permanent.get()
+    }
+    pub fn len(&self) -> usize {
+        self.contents.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.contents.is_empty()
+    }
+}
+
+// All the other str methods, through deref
+impl Deref for PermanentString {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.contents.as_ref()
+    }
 }
 
 impl Drop for PermanentString {
     fn drop(&mut self) {
-        // we do NOT want to drop self.contents here,
-        // because we want permanent references to remain valid.
+        // we do NOT want to drop self.contents if a permanent reference
+        // was given out, because it must remain valid.
+        // Otherwise, nobody can be pointing at it, so free it as usual.
+        if !self.gave_permanent.get() {
+            // Safety: contents is never touched again after this
+            unsafe { ManuallyDrop::drop(&mut self.contents) }
+        }
+    }
+}
+
+#[test]
+fn test_permanent_string_leak() {
+    let _guard = LEAK_TEST_LOCK.lock().unwrap();
+    // No permanent reference: contents are freed on drop
+    let s = PermanentString::new("temporary");
+    assert_eq!(s.get_temporary_reference(), "temporary");
+    assert!(!s.will_leak());
+    drop(s);
 
-        // We can imagine more compelx logic, for example,
-        // store a bool of whether a permanent reference
-        // was given out, and if not, drop self.contents here.
+    // Permanent reference: contents outlive the PermanentString
+    let s = PermanentString::new("forever");
+    let forever = s.get_permanent_reference();
+    assert!(s.will_leak());
+    drop(s);
+    assert_eq!(forever, "forever");
+
+    // A String can't tell us when it is dropped, but Permanent<T> (below)
+    // drops its contents the same way, and a DropLogger inside it can
+    let log = Rc::new(RefCell::new(Vec::new()));
+    drop(Permanent::new(DropLogger::new("freed", &log)));
+    assert_eq!(*log.borrow(), vec!["freed"]);
+
+    let p = Permanent::new(DropLogger::new("leaked", &log));
+    let _forever = p.get_permanent_reference();
+    drop(p);
+    assert_eq!(*log.borrow(), vec!["freed"]);
+}
+
+#[test]
+fn test_leaked_permanent_count() {
+    let _guard = LEAK_TEST_LOCK.lock().unwrap();
+    let before = leaked_permanent_count();
+
+    let s = PermanentString::new("not leaked");
+    let _ = s.get_temporary_reference();
+    drop(s);
+    assert_eq!(leaked_permanent_count(), before);
+
+    let a = PermanentString::new("a");
+    let _ = a.get_permanent_reference();
+    assert_eq!(leaked_permanent_count(), before + 1);
+    // Every handout counts, even from the same string
+    let _ = a.get_permanent_reference();
+    assert_eq!(leaked_permanent_count(), before + 2);
+
+    let b = PermanentString::new("b");
+    let _ = b.get_permanent_reference();
+    assert_eq!(leaked_permanent_count(), before + 3);
+}
+
+#[test]
+fn test_permanent_string_deref() {
+    let s = PermanentString::new("Hello");
+    assert_eq!(&*s, s.get_temporary_reference());
+    assert_eq!(s.len(), 5);
+    assert!(!s.is_empty());
+    assert!(s.starts_with("He"));
+    assert_eq!(s.to_uppercase(), "HELLO");
+    assert!(PermanentString::new("").is_empty());
+}
+
+// A use case: a string interner (think symbol tables in a parser).
+// Each distinct string is stored once, and everyone asking for it gets
+// the same &'static str back, so comparing two interned strings could
+// even be done by pointer.
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct StringInterner {
+    strings: HashMap<String, PermanentString>,
+}
+impl StringInterner {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn intern(&mut self, s: &str) -> &'static str {
+        if let Some(existing) = self.strings.get(s) {
+            return existing.get_permanent_reference();
+        }
+        let permanent = PermanentString::new(s);
+        let reference = permanent.get_permanent_reference();
+        self.strings.insert(s.to_string(), permanent);
+        reference
+    }
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[test]
+fn test_string_interner() {
+    let _guard = LEAK_TEST_LOCK.lock().unwrap();
+    let before = leaked_permanent_count();
+    let mut interner = StringInterner::new();
+    let a1 = interner.intern("apple");
+    let b = interner.intern("banana");
+    // A different allocation with the same contents
+    let apple = String::from("app") + "le";
+    let a2 = interner.intern(&apple);
+    assert!(std::ptr::eq(a1, a2));
+    assert!(!std::ptr::eq(a1, b));
+    assert_eq!(interner.len(), 2);
+    // One handout per intern() call, even for a string already in there
+    assert_eq!(leaked_permanent_count(), before + 3);
+
+    // Interned strings outlive the interner
+    drop(interner);
+    assert_eq!(a1, "apple");
+}
+
+// The same pattern works for any heap value, not just strings.
+//
+// Safety invariant: the T lives in a Box, i.e. on the heap, so moving a
+// Permanent<T> around only moves the pointer; the T itself stays at
+// contents_ptr until the Box is dropped. And once a permanent reference
+// is handed out, the Box is never dropped.
+pub struct Permanent<T> {
+    contents: ManuallyDrop<Box<T>>,
+    contents_ptr: *const T,
+    gave_permanent: Cell<bool>,
+}
+impl<T> Permanent<T> {
+    pub fn new(value: T) -> Self {
+        let contents = Box::new(value);
+        let contents_ptr: *const T = contents.as_ref();
+        Self {
+            contents: ManuallyDrop::new(contents),
+            contents_ptr,
+            gave_permanent: Cell::new(false),
+        }
+    }
+    pub fn get_temporary_reference(&self) -> &T {
+        self.contents.as_ref()
+    }
+    // Whether dropping self will leak the contents
+    pub fn will_leak(&self) -> bool {
+        self.gave_permanent.get()
+    }
+}
+// A &'static T only makes sense if T itself has no shorter lifetimes in it
+impl<T: 'static> Permanent<T> {
+    pub fn get_permanent_reference(&self) -> &'static T {
+        self.gave_permanent.set(true);
+        unsafe { self.contents_ptr.as_ref().unwrap() }
+    }
+}
+
+impl<T> Drop for Permanent<T> {
+    fn drop(&mut self) {
+        if !self.gave_permanent.get() {
+            // Safety: contents is never touched again after this
+            unsafe { ManuallyDrop::drop(&mut self.contents) }
+        }
+    }
+}
+
+#[test]
+fn test_permanent_generic() {
+    use std::rc::Rc;
+
+    // Counts how many times it has been dropped
+    struct Config {
+        verbose: bool,
+        drops: Rc<Cell<usize>>,
+    }
+    impl Drop for Config {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    let drops = Rc::new(Cell::new(0));
+    let p = Permanent::new(Config { verbose: true, drops: drops.clone() });
+    assert!(p.get_temporary_reference().verbose);
+    drop(p);
+    assert_eq!(drops.get(), 1);
+
+    let p = Permanent::new(Config { verbose: false, drops: drops.clone() });
+    let config: &'static Config = p.get_permanent_reference();
+    drop(p);
+    // Leaked on purpose: never dropped, still readable
+    assert_eq!(drops.get(), 1);
+    assert!(!config.verbose);
+}
+
+/*
+    Arenas
+
+    Allocate lots of values that all live as long as the arena, and are
+    all freed together when the arena is dropped.
+    alloc() only takes &self, so it is fine to hold on to references from
+    earlier allocations while allocating more.
+*/
+
+pub struct Arena<T> {
+    chunks: RefCell<Vec<Vec<T>>>,
+    chunk_size: usize,
+}
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Self::with_chunk_size(16)
+    }
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "Arena chunk size must be positive");
+        Self { chunks: RefCell::new(Vec::new()), chunk_size }
+    }
+    pub fn alloc(&self, value: T) -> &T {
+        let mut chunks = self.chunks.borrow_mut();
+        // Never push onto a full chunk: that would reallocate it and move
+        // every value in it. Start a new chunk instead. (Pushing onto
+        // chunks itself is fine, that only moves the Vec headers, not
+        // the values they point to.)
+        let full = chunks.last().is_none_or(|c| c.len() == c.capacity());
+        if full {
+            chunks.push(Vec::with_capacity(self.chunk_size));
+        }
+        let chunk = chunks.last_mut().unwrap();
+        chunk.push(value);
+        let ptr: *const T = chunk.last().unwrap();
+        // Safety: the value stays at ptr until the arena is dropped,
+        // since its chunk is never reallocated or shrunk, and nothing is
+        // ever removed from it.
+        unsafe { &*ptr }
+    }
+    pub fn len(&self) -> usize {
+        self.chunks.borrow().iter().map(Vec::len).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_arena() {
+    let arena = Arena::with_chunk_size(2);
+    assert!(arena.is_empty());
+    let first = arena.alloc(String::from("first"));
+    let second = arena.alloc(String::from("second"));
+    // Forces a new chunk
+    let mut later = Vec::new();
+    for i in 0..10 {
+        later.push(arena.alloc(i.to_string()));
+    }
+    assert_eq!(arena.len(), 12);
+    assert_eq!(first, "first");
+    assert_eq!(second, "second");
+    for (i, s) in later.iter().enumerate() {
+        assert_eq!(**s, i.to_string());
     }
 }
 
@@ -95,6 +443,38 @@ pub fn sizeof_operator() {
     let _size = size_of::<(i32, i8)>();
 }
 
+// Sizes of a few types worth knowing. Some things to notice:
+// - Box and Rc are a single pointer (the Rc counts live on the heap)
+// - &str is two words: pointer and length
+// - Option<Box<T>> is no bigger than Box<T>: a Box is never null, so None
+//   is stored as null (the "niche" optimization)
+pub fn type_sizes() -> Vec<(&'static str, usize)> {
+    use crate::id_manager::ID;
+    use crate::smart_pointers::FuncList;
+    use std::rc::Rc;
+
+    vec![
+        ("usize", size_of::<usize>()),
+        ("(i32, i8)", size_of::<(i32, i8)>()),
+        ("Box<i32>", size_of::<Box<i32>>()),
+        ("Rc<i32>", size_of::<Rc<i32>>()),
+        ("Option<Box<i32>>", size_of::<Option<Box<i32>>>()),
+        ("&str", size_of::<&str>()),
+        ("ID", size_of::<ID>()),
+        ("FuncList<i32>", size_of::<FuncList<i32>>()),
+    ]
+}
+
+#[test]
+fn test_type_sizes() {
+    let sizes: HashMap<_, _> = type_sizes().into_iter().collect();
+    assert_eq!(sizes["Option<Box<i32>>"], sizes["Box<i32>"]);
+    assert_eq!(sizes["Box<i32>"], size_of::<usize>());
+    assert_eq!(sizes["&str"], 2 * size_of::<usize>());
+    // Padded up to the alignment of i32
+    assert_eq!(sizes["(i32, i8)"], 8);
+}
+
 /*
     mem::replace
     https://doc.rust-lang.org/std/mem/fn.replace.html
@@ -109,3 +489,54 @@ pub fn sizeof_operator() {
     Example from the reading:
     https://rust-unofficial.github.io/too-many-lists/first-final.html
 */
+
+use std::mem;
+
+// Take v[i] out, leaving a default value in its place.
+// (mem::take(x) is short for mem::replace(x, Default::default()))
+// Panics if i is out of bounds, like indexing would.
+// Works on any slice, so a &mut Vec<T> can be passed in directly.
+pub fn take_at<T: Default>(v: &mut [T], i: usize) -> T {
+    mem::take(&mut v[i])
+}
+
+// Swap v[i] and v[j] without needing a placeholder value. Slices already
+// know how (internally they borrow both elements at once, which safe code
+// can only do by splitting the slice with split_at_mut).
+// Panics if i or j is out of bounds.
+pub fn swap_elems<T>(v: &mut [T], i: usize, j: usize) {
+    v.swap(i, j);
+}
+
+#[test]
+fn test_take_at() {
+    let mut v = vec![vec![1, 2], vec![3], vec![4, 5, 6]];
+    let taken = take_at(&mut v, 2);
+    assert_eq!(taken, vec![4, 5, 6]);
+    assert_eq!(v, vec![vec![1, 2], vec![3], vec![]]);
+}
+
+#[test]
+#[should_panic]
+fn test_take_at_out_of_bounds() {
+    let mut v = vec![String::from("only")];
+    take_at(&mut v, 1);
+}
+
+#[test]
+fn test_swap_elems() {
+    let mut v = vec!["a", "b", "c", "d"];
+    swap_elems(&mut v, 3, 0);
+    assert_eq!(v, vec!["d", "b", "c", "a"]);
+    swap_elems(&mut v, 1, 2);
+    assert_eq!(v, vec!["d", "c", "b", "a"]);
+    swap_elems(&mut v, 2, 2);
+    assert_eq!(v, vec!["d", "c", "b", "a"]);
+}
+
+#[test]
+#[should_panic]
+fn test_swap_elems_out_of_bounds() {
+    let mut v = vec![1, 2, 3];
+    swap_elems(&mut v, 0, 3);
+}
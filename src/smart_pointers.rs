@@ -76,6 +76,119 @@ fn example_box_dyn() {
     // assert!(false);
 }
 
+// Putting Box<dyn Fn> to use: a table of event handlers, each under an
+// ID from id_manager.
+// The IDManagers can't be reused for this: they also map items back to
+// IDs, which needs Eq + Hash items, and closures are neither. So this is
+// a one-way map from ID to callback, handing out IDs itself.
+use crate::id_manager::ID;
+
+#[derive(Default)]
+pub struct CallbackRegistry {
+    next_id: ID,
+    callbacks: HashMap<ID, Box<dyn Fn() -> usize>>,
+}
+impl CallbackRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn register<F: Fn() -> usize + 'static>(&mut self, f: F) -> ID {
+        let id = self.next_id;
+        self.callbacks.insert(id, Box::new(f));
+        self.next_id.step();
+        id
+    }
+    // None if nothing is registered under id
+    pub fn call(&self, id: ID) -> Option<usize> {
+        self.callbacks.get(&id).map(|f| f())
+    }
+    // Whether there was a callback to remove
+    pub fn unregister(&mut self, id: ID) -> bool {
+        self.callbacks.remove(&id).is_some()
+    }
+    pub fn len(&self) -> usize {
+        self.callbacks.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty()
+    }
+}
+
+#[test]
+fn test_callback_registry() {
+    let mut registry = CallbackRegistry::new();
+    let three = registry.register(|| 3);
+    let base = 40;
+    let answer = registry.register(move || base + 2);
+    assert_ne!(three, answer);
+    assert_eq!(registry.call(three), Some(3));
+    assert_eq!(registry.call(answer), Some(42));
+
+    assert!(registry.unregister(three));
+    assert!(!registry.unregister(three));
+    assert_eq!(registry.call(three), None);
+    assert_eq!(registry.call(answer), Some(42));
+    assert_eq!(registry.len(), 1);
+}
+
+// Same idea for values: a Box<dyn Debug> can only ever be printed again,
+// but a Box<dyn Any> remembers its type at runtime, and downcast_ref gives
+// back a &T if (and only if) T is the right type.
+use std::any::Any;
+
+#[derive(Default)]
+pub struct AnyStore {
+    next_id: ID,
+    values: HashMap<ID, Box<dyn Any>>,
+}
+impl AnyStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn insert<T: Any>(&mut self, value: T) -> ID {
+        let id = self.next_id;
+        self.values.insert(id, Box::new(value));
+        self.next_id.step();
+        id
+    }
+    // None if id is unknown, or if the value there is not a T
+    pub fn get<T: Any>(&self, id: ID) -> Option<&T> {
+        self.values.get(&id)?.downcast_ref()
+    }
+    pub fn get_mut<T: Any>(&mut self, id: ID) -> Option<&mut T> {
+        self.values.get_mut(&id)?.downcast_mut()
+    }
+    pub fn remove(&mut self, id: ID) -> bool {
+        self.values.remove(&id).is_some()
+    }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+#[test]
+fn test_any_store() {
+    let mut store = AnyStore::new();
+    let number = store.insert(3i32);
+    let text = store.insert("hello".to_string());
+
+    assert_eq!(store.get::<i32>(number), Some(&3));
+    assert_eq!(store.get::<String>(text), Some(&"hello".to_string()));
+    // Wrong type: no garbage, just None
+    assert_eq!(store.get::<String>(number), None);
+    assert_eq!(store.get::<i64>(number), None);
+    assert_eq!(store.get::<&str>(text), None);
+
+    store.get_mut::<String>(text).unwrap().push_str(" world");
+    assert_eq!(store.get::<String>(text).unwrap(), "hello world");
+    assert!(store.remove(number));
+    assert_eq!(store.get::<i32>(number), None);
+    assert_eq!(store.len(), 1);
+}
+
 /*
     The one place that Box shows up a lot:
     recursive data types
@@ -93,6 +206,382 @@ pub enum FuncList<T> {
 // size_of(FuncList<T>) = size_of(T) + size_of(pointer)
 // (roughtly size of T plus a byte)
 
+use std::fmt;
+use std::iter::FromIterator;
+use std::mem::{self, ManuallyDrop};
+use std::ptr;
+
+impl<T> FuncList<T> {
+    // Borrowing iterator over the elements, front to back
+    pub fn iter(&self) -> FuncListIter<'_, T> {
+        FuncListIter { current: self }
+    }
+    // Same, handing out &mut T to modify the elements in place
+    pub fn iter_mut(&mut self) -> FuncListIterMut<'_, T> {
+        FuncListIterMut { current: Some(self) }
+    }
+
+    // Counts the Cons cells with a loop (via iter), not recursion
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+    pub fn is_empty(&self) -> bool {
+        matches!(self, FuncList::Nil)
+    }
+    // Element at index n, walking n Cons cells (None if too short)
+    pub fn get(&self, n: usize) -> Option<&T> {
+        self.iter().nth(n)
+    }
+    // Searching, front to back (again a loop via iter)
+    pub fn contains(&self, x: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.iter().any(|y| y == x)
+    }
+    // Index of the first element equal to x
+    pub fn position(&self, x: &T) -> Option<usize>
+    where
+        T: PartialEq,
+    {
+        self.iter().position(|y| y == x)
+    }
+
+    // Conversions from/to Vec, keeping the order.
+    // from_vec builds back to front, so each element is pushed on the front.
+    pub fn from_vec(v: Vec<T>) -> FuncList<T> {
+        v.into_iter().rev().fold(FuncList::Nil, FuncList::push_front)
+    }
+    pub fn to_vec(self) -> Vec<T> {
+        self.into_iter().collect()
+    }
+
+    // Apply f to every element, front to back.
+    // Goes through a Vec so that no step is recursive.
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> FuncList<U> {
+        FuncList::from_vec(self.to_vec().into_iter().map(f).collect())
+    }
+    // Combine the elements left to right, starting from init
+    pub fn fold<B, F: FnMut(B, T) -> B>(self, init: B, f: F) -> B {
+        self.into_iter().fold(init, f)
+    }
+    // Keep the elements matching f, in order
+    pub fn filter<F: FnMut(&T) -> bool>(self, f: F) -> FuncList<T> {
+        self.into_iter().filter(f).collect()
+    }
+
+    // Cons each element onto an accumulator: the first element ends up
+    // at the back. O(n), no recursion.
+    pub fn reverse(self) -> FuncList<T> {
+        self.into_iter().fold(FuncList::Nil, FuncList::push_front)
+    }
+
+    // Concatenate: self's elements followed by other's.
+    // Reverse self, then cons its elements onto other one by one
+    // (the naive recursive version overflows on long lists).
+    pub fn append(self, other: FuncList<T>) -> FuncList<T> {
+        self.reverse().into_iter().fold(other, FuncList::push_front)
+    }
+
+    // Using the list as a stack
+    pub fn push_front(self, value: T) -> FuncList<T> {
+        FuncList::Cons(value, Box::new(self))
+    }
+    pub fn pop_front(self) -> (Option<T>, FuncList<T>) {
+        match self.into_head_tail() {
+            Some((head, tail)) => (Some(head), tail),
+            None => (None, FuncList::Nil),
+        }
+    }
+
+    // Auxiliary method: split a Cons cell into its parts.
+    // FuncList implements Drop (below), and Rust won't let us move fields
+    // out of such a type with a plain match. So instead we wrap self in
+    // ManuallyDrop, which turns off its destructor, and read the fields
+    // out by hand.
+    fn into_head_tail(self) -> Option<(T, FuncList<T>)> {
+        let mut list = ManuallyDrop::new(self);
+        match &mut *list {
+            FuncList::Nil => None,
+            FuncList::Cons(head, tail) => unsafe {
+                // Safety: each field is read exactly once, and the
+                // original list is never dropped, so nothing is freed twice
+                let head = ptr::read(head);
+                let tail = ptr::read(tail);
+                Some((head, *tail))
+            },
+        }
+    }
+}
+
+// Owning iterator: peels off one Cons cell at a time
+pub struct FuncListIntoIter<T> {
+    list: FuncList<T>,
+}
+
+impl<T> Iterator for FuncListIntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        let list = mem::replace(&mut self.list, FuncList::Nil);
+        let (head, tail) = list.into_head_tail()?;
+        self.list = tail;
+        Some(head)
+    }
+}
+
+impl<T> IntoIterator for FuncList<T> {
+    type Item = T;
+    type IntoIter = FuncListIntoIter<T>;
+    fn into_iter(self) -> FuncListIntoIter<T> {
+        FuncListIntoIter { list: self }
+    }
+}
+
+// A list is built back to front, so we need all the elements first
+impl<T> FromIterator<T> for FuncList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(items: I) -> Self {
+        FuncList::from_vec(items.into_iter().collect())
+    }
+}
+
+// Derived PartialEq and Debug would recurse down the Box chain, same as
+// the derived Drop, so write them with iterators instead.
+// Debug prints like a Vec: [1, 2, 3]
+impl<T: PartialEq> PartialEq for FuncList<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for FuncList<T> {}
+
+// Not derived, for the same reason as Drop below: the derived clone
+// recurses down the list. Collecting goes through a Vec instead.
+impl<T: Clone> Clone for FuncList<T> {
+    fn clone(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for FuncList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+// The compiler-generated drop is recursive: dropping a Cons drops its
+// Box, which drops the next Cons, and so on -- one stack frame per
+// element, which overflows the stack for long lists.
+// Instead, walk the list in a loop, unhooking each tail before the
+// node in front of it is dropped.
+// (Same trick as in "too many lists", using mem::replace.)
+impl<T> Drop for FuncList<T> {
+    fn drop(&mut self) {
+        if let FuncList::Cons(_, tail) = self {
+            let mut current = mem::replace(&mut **tail, FuncList::Nil);
+            while let FuncList::Cons(_, tail) = &mut current {
+                let next = mem::replace(&mut **tail, FuncList::Nil);
+                // The old node now has a Nil tail, so dropping it is cheap
+                current = next;
+            }
+        }
+    }
+}
+
+// The iterator just remembers which node it is at
+pub struct FuncListIter<'a, T> {
+    current: &'a FuncList<T>,
+}
+
+impl<'a, T> Iterator for FuncListIter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        match self.current {
+            FuncList::Nil => None,
+            FuncList::Cons(head, tail) => {
+                self.current = tail;
+                Some(head)
+            }
+        }
+    }
+}
+
+// The mutable version can't just copy the &mut to the current node
+// (two &mut to the same node!), so it take()s it out of an Option
+// instead, moving it into the match.
+pub struct FuncListIterMut<'a, T> {
+    current: Option<&'a mut FuncList<T>>,
+}
+
+impl<'a, T> Iterator for FuncListIterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<&'a mut T> {
+        match self.current.take()? {
+            FuncList::Nil => None,
+            FuncList::Cons(head, tail) => {
+                self.current = Some(&mut **tail);
+                Some(head)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_funclist_iter() {
+    use FuncList::{Cons, Nil};
+
+    let list = Cons(1, Box::new(Cons(2, Box::new(Cons(3, Box::new(Nil))))));
+    let v: Vec<i32> = list.iter().copied().collect();
+    assert_eq!(v, vec![1, 2, 3]);
+    assert_eq!(FuncList::<i32>::Nil.iter().next(), None);
+}
+
+#[test]
+fn test_funclist_push_pop() {
+    let list = FuncList::Nil.push_front(2).push_front(1);
+    let (head, rest) = list.pop_front();
+    assert_eq!(head, Some(1));
+    let (head, rest) = rest.pop_front();
+    assert_eq!(head, Some(2));
+
+    let (head, rest) = rest.pop_front();
+    assert_eq!(head, None);
+    assert!(matches!(rest, FuncList::Nil));
+}
+
+#[test]
+fn test_funclist_len() {
+    let empty: FuncList<u8> = FuncList::Nil;
+    assert_eq!(empty.len(), 0);
+    assert!(empty.is_empty());
+
+    let list = FuncList::Nil.push_front(3).push_front(2).push_front(1);
+    assert_eq!(list.len(), 3);
+    assert!(!list.is_empty());
+}
+
+#[test]
+fn test_funclist_vec_round_trip() {
+    for v in [vec![], vec![1], vec![1, 2, 3], (0..1000).collect()] {
+        assert_eq!(FuncList::from_vec(v.clone()).to_vec(), v);
+    }
+}
+
+#[test]
+fn test_funclist_map() {
+    let list = FuncList::from_vec(vec![1, 2, 3]);
+    let strings = list.map(|x| format!("<{}>", x));
+    assert_eq!(strings.to_vec(), vec!["<1>", "<2>", "<3>"]);
+
+    let long = FuncList::from_vec((0..1_000_000).collect()).map(|x| x + 1);
+    assert_eq!(long.len(), 1_000_000);
+}
+
+#[test]
+fn test_funclist_collect() {
+    let v = vec!["a", "b", "c"];
+    let list: FuncList<&str> = v.iter().copied().collect();
+    assert_eq!(list.len(), 3);
+    let back: Vec<&str> = list.into_iter().collect();
+    assert_eq!(back, v);
+}
+
+#[test]
+fn test_funclist_reverse() {
+    let list = FuncList::from_vec(vec![1, 2, 3]);
+    let reversed = list.reverse();
+    assert_eq!(reversed.iter().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+    assert_eq!(reversed.reverse().to_vec(), vec![1, 2, 3]);
+    assert!(FuncList::<i32>::Nil.reverse().is_empty());
+}
+
+#[test]
+fn test_funclist_append() {
+    let a = FuncList::from_vec(vec![1, 2]);
+    let b = FuncList::from_vec(vec![3, 4]);
+    assert_eq!(a.append(b).to_vec(), vec![1, 2, 3, 4]);
+
+    let a = FuncList::from_vec(vec![1, 2]);
+    assert_eq!(a.append(FuncList::Nil).to_vec(), vec![1, 2]);
+    let b = FuncList::from_vec(vec![3, 4]);
+    assert_eq!(FuncList::Nil.append(b).to_vec(), vec![3, 4]);
+}
+
+#[test]
+fn test_funclist_eq_debug() {
+    let a = FuncList::from_vec(vec![1, 2, 3]);
+    let b = FuncList::Nil.push_front(3).push_front(2).push_front(1);
+    assert_eq!(a, b);
+    assert_ne!(a, FuncList::from_vec(vec![1, 2]));
+    assert_ne!(FuncList::from_vec(vec![1, 2]), a);
+    assert_eq!(format!("{:?}", a), "[1, 2, 3]");
+    assert_eq!(format!("{:?}", FuncList::<i32>::Nil), "[]");
+}
+
+#[test]
+fn test_funclist_get() {
+    let list = FuncList::from_vec(vec!['a', 'b', 'c']);
+    assert_eq!(list.get(0), Some(&'a'));
+    assert_eq!(list.get(2), Some(&'c'));
+    assert_eq!(list.get(3), None);
+    assert_eq!(FuncList::<char>::Nil.get(0), None);
+}
+
+#[test]
+fn test_funclist_fold_filter() {
+    let list: FuncList<i32> = (1..=10).collect();
+    assert_eq!(list.fold(0, |acc, x| acc + x), 55);
+
+    let list: FuncList<i32> = (1..=10).collect();
+    assert_eq!(list.filter(|x| x % 2 == 0).to_vec(), vec![2, 4, 6, 8, 10]);
+}
+
+#[test]
+fn test_funclist_iter_mut() {
+    let mut list = FuncList::from_vec(vec![1, 2, 3]);
+    for x in list.iter_mut() {
+        *x *= 2;
+    }
+    assert_eq!(list.iter().copied().collect::<Vec<_>>(), vec![2, 4, 6]);
+    assert_eq!(FuncList::<i32>::Nil.iter_mut().next(), None);
+}
+
+#[test]
+fn test_funclist_contains_position() {
+    let list = FuncList::from_vec(vec!['a', 'b', 'c', 'b']);
+    assert!(list.contains(&'c'));
+    assert!(!list.contains(&'z'));
+    assert_eq!(list.position(&'a'), Some(0));
+    assert_eq!(list.position(&'b'), Some(1));
+    assert_eq!(list.position(&'z'), None);
+    assert!(!FuncList::Nil.contains(&'a'));
+}
+
+#[test]
+fn test_funclist_clone() {
+    let original = FuncList::from_vec(vec![1, 2, 3]);
+    let mut copy = original.clone();
+    assert_eq!(copy, original);
+    for x in copy.iter_mut() {
+        *x += 10;
+    }
+    assert_eq!(original.to_vec(), vec![1, 2, 3]);
+    assert_eq!(copy.to_vec(), vec![11, 12, 13]);
+
+    let long: FuncList<i32> = (0..1_000_000).collect();
+    assert_eq!(long.clone().len(), 1_000_000);
+}
+
+#[test]
+fn test_funclist_drop_long() {
+    let mut list = FuncList::Nil;
+    for i in 0..1_000_000 {
+        list = list.push_front(i);
+    }
+    // Would overflow the stack with the derived (recursive) drop
+    drop(list);
+}
+
 /*
     Shared ownership: Rc and Arc
 
@@ -102,6 +591,104 @@ pub enum FuncList<T> {
     (You would use Arc for concurrent programming with shared ownership)
 */
 
+// Example: a persistent list, where lists share their tails.
+// FuncList::push_front consumes the list; here push_front only needs
+// &self, because the new head points at the *same* tail (one more Rc)
+// instead of taking ownership of it. Both the old and the new list stay
+// usable, and nothing is copied.
+// Following https://rust-unofficial.github.io/too-many-lists/third.html
+use std::rc::Rc;
+
+pub struct RcList<T> {
+    head: Option<Rc<RcNode<T>>>,
+}
+
+struct RcNode<T> {
+    value: T,
+    next: Option<Rc<RcNode<T>>>,
+}
+
+impl<T> RcList<T> {
+    pub fn new() -> Self {
+        RcList { head: None }
+    }
+    pub fn push_front(&self, value: T) -> RcList<T> {
+        RcList {
+            head: Some(Rc::new(RcNode { value, next: self.head.clone() })),
+        }
+    }
+    pub fn head(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+    // Everything but the head, again without copying
+    pub fn tail(&self) -> RcList<T> {
+        RcList { head: self.head.as_ref().and_then(|node| node.next.clone()) }
+    }
+    pub fn iter(&self) -> RcListIter<'_, T> {
+        RcListIter { current: self.head.as_deref() }
+    }
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+}
+
+impl<T> Default for RcList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Iterative drop, as for FuncList. A node is only freed if this list was
+// its last owner; as soon as we reach a node someone else still shares,
+// stop, since the rest of the list is theirs too.
+impl<T> Drop for RcList<T> {
+    fn drop(&mut self) {
+        let mut current = self.head.take();
+        while let Some(node) = current {
+            match Rc::try_unwrap(node) {
+                Ok(mut node) => current = node.next.take(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+pub struct RcListIter<'a, T> {
+    current: Option<&'a RcNode<T>>,
+}
+
+impl<'a, T> Iterator for RcListIter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.current?;
+        self.current = node.next.as_deref();
+        Some(&node.value)
+    }
+}
+
+#[test]
+fn test_rc_list_sharing() {
+    let shared = RcList::new().push_front(3).push_front(2);
+    let a = shared.push_front(1);
+    let b = shared.push_front(10).push_front(20);
+    assert_eq!(a.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(b.iter().copied().collect::<Vec<_>>(), vec![20, 10, 2, 3]);
+    // The shared part is still there on its own, too
+    assert_eq!(shared.iter().copied().collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!((a.len(), b.len(), shared.len()), (3, 4, 2));
+
+    // Literally the same nodes
+    let a_tail = a.tail();
+    assert!(std::ptr::eq(a_tail.head().unwrap(), shared.head().unwrap()));
+    drop(shared);
+    assert_eq!(b.tail().tail().iter().copied().collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(a.head(), Some(&1));
+    assert!(RcList::<i32>::new().tail().is_empty());
+}
+
 /*
     Interior Mutability: Cell and RefCell
 
@@ -125,33 +712,637 @@ pub enum FuncList<T> {
     with mutable fields.
 */
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+// The simplest use of a Cell: a counter that can be bumped through a
+// shared reference. Handy for statistics on an otherwise immutable
+// object, as in the Cache below.
+#[derive(Debug, Default)]
+pub struct Counter {
+    count: Cell<usize>,
+}
+impl Counter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn increment(&self) {
+        self.add(1);
+    }
+    pub fn add(&self, n: usize) {
+        self.count.set(self.count.get() + n);
+    }
+    pub fn get(&self) -> usize {
+        self.count.get()
+    }
+    pub fn reset(&self) {
+        self.count.set(0);
+    }
+}
+
+#[test]
+fn test_counter() {
+    let counter = Counter::new();
+    let shared: &Counter = &counter;
+    let also_shared: &Counter = &counter;
+    shared.increment();
+    also_shared.increment();
+    shared.add(5);
+    assert_eq!(counter.get(), 7);
+    also_shared.reset();
+    assert_eq!(shared.get(), 0);
+}
 
 // Suppose we have a Cache
 // and we also internally want to track cache hits and cache misses
 // transparently, without exposing that to the user
 // -> only do this debug mode, print it out to a log
-pub struct Cache {
-    cache: HashMap<usize, usize>,
-    hits: Cell<usize>,
-    misses: Cell<usize>,
-}
-impl Cache {
-    pub fn save(&mut self, x: usize, y: usize) {
-        self.cache.insert(x, y);
-    }
-    pub fn query(&self, x: usize) -> Option<usize> {
-        match self.cache.get(&x) {
-            Some(&x) => {
-                self.hits.set(self.hits.get() + 1);
-                Some(x)
+//
+// query only gets &self, so it hands back a clone of the value
+// (hence V: Clone) rather than a reference.
+//
+// A cache can optionally be bounded (see with_capacity), evicting a
+// key when full. Which key is decided by an EvictionPolicy (least
+// recently used by default). query has to keep the policy up to date
+// through &self -- interior mutability again, this time with a RefCell
+// since a policy is not Copy.
+//
+// Entries can also be given a time-to-live (see with_ttl). Each value
+// is stored with the Instant it was saved; query treats an entry older
+// than the TTL as a miss. query can't remove it through &self, so it
+// only notes the key down, and the entry is actually evicted on the
+// next save.
+pub struct Cache<K, V, P = Lru<K>>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    P: EvictionPolicy<K>,
+{
+    cache: HashMap<K, (V, Instant)>,
+    hits: Counter,
+    misses: Counter,
+    capacity: Option<usize>,
+    policy: RefCell<P>,
+    ttl: Option<Duration>,
+    expired: RefCell<HashSet<K>>,
+}
+impl<K, V, P> Default for Cache<K, V, P>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    P: EvictionPolicy<K> + Default,
+{
+    fn default() -> Self {
+        Self {
+            cache: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+            capacity: None,
+            policy: Default::default(),
+            ttl: None,
+            expired: Default::default(),
+        }
+    }
+}
+// Constructors for the default (LRU) policy
+impl<K, V> Cache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+    // A cache holding at most capacity entries
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_policy(capacity, Lru::default())
+    }
+    // A cache whose entries go stale ttl after being saved
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self { ttl: Some(ttl), ..Default::default() }
+    }
+}
+impl<K, V, P> Cache<K, V, P>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    P: EvictionPolicy<K>,
+{
+    // A cache holding at most capacity entries, evicting according to
+    // the given policy
+    pub fn with_policy(capacity: usize, policy: P) -> Self {
+        assert!(capacity > 0, "Cache capacity must be positive");
+        Self {
+            cache: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+            capacity: Some(capacity),
+            policy: RefCell::new(policy),
+            ttl: None,
+            expired: Default::default(),
+        }
+    }
+    // Returns the value k had before, like HashMap::insert (a stale value
+    // doesn't count: it was no longer cached as far as query knew)
+    pub fn save(&mut self, k: K, v: V) -> Option<V> {
+        self.evict_expired();
+        if let Some(capacity) = self.capacity {
+            let policy = self.policy.get_mut();
+            if self.cache.contains_key(&k) {
+                policy.on_access(&k);
+            } else {
+                if self.cache.len() >= capacity {
+                    // Full: the policy picks who goes
+                    if let Some(victim) = policy.evict() {
+                        self.cache.remove(&victim);
+                    }
+                }
+                policy.on_insert(&k);
+            }
+        }
+        let (old, saved) = self.cache.insert(k, (v, Instant::now()))?;
+        if self.is_expired(saved) {
+            None
+        } else {
+            Some(old)
+        }
+    }
+    // Note: on a bounded cache, a hit is also reported to the policy
+    // (e.g. LRU marks k as most recently used)
+    pub fn query(&self, k: &K) -> Option<V> {
+        match self.cache.get(k) {
+            Some((v, saved)) if !self.is_expired(*saved) => {
+                self.hits.increment();
+                if self.capacity.is_some() {
+                    self.policy.borrow_mut().on_access(k);
+                }
+                Some(v.clone())
             }
-            None => {
-                self.misses.set(self.misses.get() + 1);
+            entry => {
+                if entry.is_some() {
+                    // Stale: remember to evict it later (a set, so
+                    // querying it again doesn't queue it again)
+                    self.expired.borrow_mut().insert(k.clone());
+                }
+                self.misses.increment();
                 None
             }
         }
     }
+    // Look at a cached value without counting a hit or miss (and without
+    // updating recency). No clone needed here: we aren't touching any
+    // Cell, so a plain &V borrowed from self is fine.
+    pub fn peek(&self, k: &K) -> Option<&V> {
+        match self.cache.get(k) {
+            Some((v, saved)) if !self.is_expired(*saved) => Some(v),
+            _ => None,
+        }
+    }
+    // All (live) entries, in no particular order. Like peek, this doesn't
+    // count as a query.
+    pub fn entries(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.cache
+            .iter()
+            .filter(move |(_, (_, saved))| !self.is_expired(*saved))
+            .map(|(k, (v, _))| (k, v))
+    }
+
+    // Drop k's entry, e.g. because the data it was computed from changed.
+    // Returns whether there was one. Not a query: hits and misses are
+    // left alone.
+    pub fn invalidate(&mut self, k: &K) -> bool {
+        self.remove_entry(k).is_some()
+    }
+    // Number of stored entries. With a TTL, this includes stale entries
+    // that haven't been evicted yet.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    // Memoization: return the cached value for k (a hit), or compute it
+    // with f, save it, and return it (a miss)
+    pub fn get_or_compute<F: FnOnce() -> V>(&mut self, k: K, f: F) -> V {
+        if let Some(v) = self.query(&k) {
+            return v;
+        }
+        let v = f();
+        self.save(k, v.clone());
+        v
+    }
+
+    // Statistics
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+    pub fn misses(&self) -> usize {
+        self.misses.get()
+    }
+    // Fraction of queries that were hits (0.0 if nothing was queried yet)
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits() + self.misses();
+        if total == 0 {
+            0.0
+        } else {
+            self.hits() as f64 / total as f64
+        }
+    }
+    // Zero the counters, keeping the cached entries.
+    // Counters only need &self, so &self is enough here too.
+    pub fn reset_stats(&self) {
+        self.hits.reset();
+        self.misses.reset();
+    }
+
+    // Auxiliary methods
+    fn is_expired(&self, saved: Instant) -> bool {
+        self.ttl.is_some_and(|ttl| saved.elapsed() >= ttl)
+    }
+    // Evict the stale entries that query has run into
+    fn evict_expired(&mut self) {
+        for k in mem::take(self.expired.get_mut()) {
+            let stale = self
+                .cache
+                .get(&k)
+                .is_some_and(|(_, saved)| self.is_expired(*saved));
+            if stale {
+                self.remove_entry(&k);
+            }
+        }
+    }
+    fn remove_entry(&mut self, k: &K) -> Option<V> {
+        let (v, _) = self.cache.remove(k)?;
+        if self.capacity.is_some() {
+            self.policy.get_mut().on_remove(k);
+        }
+        Some(v)
+    }
+}
+
+/*
+    Eviction policies
+
+    A bounded Cache tells its policy about every key it stores, reads,
+    and removes, and asks it which key to throw out when full.
+    The policy only ever sees keys, never values.
+*/
+
+pub trait EvictionPolicy<K> {
+    // k was read by a query, or overwritten by a save
+    fn on_access(&mut self, k: &K);
+    // k was saved for the first time
+    fn on_insert(&mut self, k: &K);
+    // k was removed from the cache (other than through evict)
+    fn on_remove(&mut self, k: &K);
+    // Pick a key to evict, forgetting about it
+    fn evict(&mut self) -> Option<K>;
+}
+
+// Auxiliary function: remove k from a queue of keys
+fn remove_key<K: Eq>(queue: &mut VecDeque<K>, k: &K) -> Option<K> {
+    let pos = queue.iter().position(|x| x == k)?;
+    queue.remove(pos)
+}
+
+// Least recently used: keys ordered by last use, least recent at the
+// front
+pub struct Lru<K> {
+    order: VecDeque<K>,
+}
+impl<K> Default for Lru<K> {
+    fn default() -> Self {
+        Self { order: Default::default() }
+    }
+}
+impl<K: Eq + Clone> EvictionPolicy<K> for Lru<K> {
+    fn on_access(&mut self, k: &K) {
+        if let Some(key) = remove_key(&mut self.order, k) {
+            self.order.push_back(key);
+        }
+    }
+    fn on_insert(&mut self, k: &K) {
+        self.order.push_back(k.clone());
+    }
+    fn on_remove(&mut self, k: &K) {
+        remove_key(&mut self.order, k);
+    }
+    fn evict(&mut self) -> Option<K> {
+        self.order.pop_front()
+    }
+}
+
+// First in, first out: keys ordered by insertion, reads don't matter
+pub struct Fifo<K> {
+    order: VecDeque<K>,
+}
+impl<K> Default for Fifo<K> {
+    fn default() -> Self {
+        Self { order: Default::default() }
+    }
+}
+impl<K: Eq + Clone> EvictionPolicy<K> for Fifo<K> {
+    fn on_access(&mut self, _k: &K) {}
+    fn on_insert(&mut self, k: &K) {
+        self.order.push_back(k.clone());
+    }
+    fn on_remove(&mut self, k: &K) {
+        remove_key(&mut self.order, k);
+    }
+    fn evict(&mut self) -> Option<K> {
+        self.order.pop_front()
+    }
+}
+
+#[test]
+fn test_cache_generic() {
+    #[derive(Clone, Debug, PartialEq)]
+    struct Profile {
+        age: u8,
+    }
+
+    let mut cache = Cache::new();
+    cache.save("alice".to_string(), Profile { age: 30 });
+    assert_eq!(cache.query(&"alice".to_string()), Some(Profile { age: 30 }));
+    assert_eq!(cache.query(&"bob".to_string()), None);
+    assert_eq!(cache.hits.get(), 1);
+    assert_eq!(cache.misses.get(), 1);
+}
+
+#[test]
+fn test_cache_stats() {
+    let mut cache = Cache::new();
+    assert_eq!(cache.hit_ratio(), 0.0);
+    cache.save(1, 10);
+    cache.query(&1);
+    cache.query(&1);
+    cache.query(&1);
+    cache.query(&2);
+    assert_eq!(cache.hits(), 3);
+    assert_eq!(cache.misses(), 1);
+    assert!((cache.hit_ratio() - 0.75).abs() < 1e-9);
+}
+
+#[test]
+fn test_cache_reset_stats() {
+    let mut cache = Cache::new();
+    cache.save(1, 10);
+    cache.query(&1);
+    cache.query(&2);
+
+    // Through a shared reference
+    let shared: &Cache<usize, usize> = &cache;
+    shared.reset_stats();
+    assert_eq!(cache.hits(), 0);
+    assert_eq!(cache.misses(), 0);
+    assert_eq!(cache.hit_ratio(), 0.0);
+    assert_eq!(cache.query(&1), Some(10));
+}
+
+#[test]
+fn test_cache_get_or_compute() {
+    let mut cache = Cache::new();
+    let mut calls = 0;
+    let first = cache.get_or_compute(7, || {
+        calls += 1;
+        49
+    });
+    let second = cache.get_or_compute(7, || {
+        calls += 1;
+        49
+    });
+    assert_eq!((first, second), (49, 49));
+    assert_eq!(calls, 1);
+    assert_eq!(cache.misses(), 1);
+    assert_eq!(cache.hits(), 1);
+}
+
+#[test]
+fn test_cache_ttl() {
+    use std::thread::sleep;
+
+    let mut cache = Cache::with_ttl(Duration::from_millis(20));
+    cache.save(1, "one");
+    assert_eq!(cache.query(&1), Some("one"));
+    sleep(Duration::from_millis(40));
+    assert_eq!(cache.query(&1), None);
+    assert_eq!(cache.hits(), 1);
+    assert_eq!(cache.misses(), 1);
+
+    // Evicted lazily by the next save
+    assert_eq!(cache.cache.len(), 1);
+    cache.save(2, "two");
+    assert_eq!(cache.cache.len(), 1);
+    assert_eq!(cache.query(&2), Some("two"));
+}
+
+#[test]
+fn test_cache_ttl_repeated_stale_query() {
+    let mut cache = Cache::with_ttl(Duration::from_millis(10));
+    cache.save(1, "one");
+    std::thread::sleep(Duration::from_millis(30));
+    for _ in 0..1000 {
+        assert_eq!(cache.query(&1), None);
+    }
+    assert_eq!(cache.expired.borrow().len(), 1);
+    cache.save(2, "two");
+    assert!(cache.expired.borrow().is_empty());
+}
+
+#[test]
+fn test_cache_peek() {
+    let mut cache = Cache::new();
+    cache.save(1, 10);
+    assert_eq!(cache.peek(&1), Some(&10));
+    assert_eq!(cache.peek(&2), None);
+    assert_eq!(cache.peek(&1), Some(&10));
+    assert_eq!((cache.hits(), cache.misses()), (0, 0));
+
+    cache.query(&1);
+    cache.query(&2);
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+}
+
+#[test]
+fn test_cache_policies() {
+    // Same pattern for both: a, b saved; a read; c saved
+    fn run<P: EvictionPolicy<&'static str>>(policy: P) -> Vec<&'static str> {
+        let mut cache = Cache::with_policy(2, policy);
+        cache.save("a", 1);
+        cache.save("b", 2);
+        cache.query(&"a");
+        cache.save("c", 3);
+        let mut kept: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .copied()
+            .filter(|k| cache.peek(k).is_some())
+            .collect();
+        kept.sort_unstable();
+        kept
+    }
+    // LRU throws out b (a was used more recently)...
+    assert_eq!(run(Lru::default()), vec!["a", "c"]);
+    // ...FIFO throws out a (it came in first)
+    assert_eq!(run(Fifo::default()), vec!["b", "c"]);
+}
+
+#[test]
+fn test_cache_lru() {
+    let mut cache = Cache::with_capacity(2);
+    cache.save("a", 1);
+    cache.save("b", 2);
+    // a is now more recent than b
+    assert_eq!(cache.query(&"a"), Some(1));
+    cache.save("c", 3);
+    assert_eq!(cache.query(&"b"), None);
+    assert_eq!(cache.query(&"a"), Some(1));
+    assert_eq!(cache.query(&"c"), Some(3));
+
+    // Overwriting a key doesn't evict anything
+    cache.save("c", 4);
+    assert_eq!(cache.query(&"a"), Some(1));
+    assert_eq!(cache.query(&"c"), Some(4));
+}
+
+#[test]
+fn test_cache_invalidate() {
+    let mut cache = Cache::with_capacity(2);
+    cache.save("a", 1);
+    cache.save("b", 2);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.invalidate(&"a"));
+    assert!(!cache.invalidate(&"a"));
+    assert_eq!(cache.len(), 1);
+    assert_eq!((cache.hits(), cache.misses()), (0, 0));
+    assert_eq!(cache.query(&"a"), None);
+    assert_eq!(cache.misses(), 1);
+
+    // The policy forgot about a too: saving two more only evicts b
+    cache.save("c", 3);
+    assert_eq!(cache.len(), 2);
+    cache.save("d", 4);
+    assert_eq!(cache.peek(&"b"), None);
+    assert_eq!(cache.peek(&"c"), Some(&3));
+    assert!(!cache.is_empty());
+}
+
+#[test]
+fn test_cache_save_returns_old() {
+    let mut cache = Cache::new();
+    assert_eq!(cache.save("k", 1), None);
+    assert_eq!(cache.save("k", 2), Some(1));
+    assert_eq!(cache.save("other", 3), None);
+    assert_eq!(cache.query(&"k"), Some(2));
+
+    let mut cache = Cache::with_ttl(Duration::from_millis(10));
+    cache.save("k", 1);
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(cache.save("k", 2), None);
+}
+
+#[test]
+fn test_cache_entries() {
+    let mut cache = Cache::new();
+    cache.save(1, "one");
+    cache.save(2, "two");
+    cache.save(3, "three");
+    let mut entries: Vec<_> = cache.entries().collect();
+    entries.sort_unstable();
+    assert_eq!(entries, vec![(&1, &"one"), (&2, &"two"), (&3, &"three")]);
+    assert_eq!((cache.hits(), cache.misses()), (0, 0));
+}
+
+/*
+    Thread-safe Cache
+
+    Cell and RefCell are not Sync: their checks are not safe to do from
+    two threads at once. The thread-safe counterparts:
+
+    - Cell<usize>  ->  AtomicUsize
+    - RefCell<T>   ->  RwLock<T> (or Mutex<T>)
+
+    RwLock allows many readers OR one writer at a time (the borrow rules
+    again, checked at runtime), so concurrent queries don't block each
+    other. Everything takes &self; share the cache with Arc.
+*/
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+pub struct ConcurrentCache<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    cache: RwLock<HashMap<K, V>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+impl<K, V> Default for ConcurrentCache<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self {
+            cache: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+}
+impl<K, V> ConcurrentCache<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn save(&self, k: K, v: V) {
+        self.cache.write().unwrap().insert(k, v);
+    }
+    pub fn query(&self, k: &K) -> Option<V> {
+        let result = self.cache.read().unwrap().get(k).cloned();
+        let counter = if result.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn test_concurrent_cache() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let cache = Arc::new(ConcurrentCache::new());
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..100 {
+                    cache.save((t, i), i * i);
+                    assert_eq!(cache.query(&(t, i)), Some(i * i));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(cache.hits(), 400);
+    assert_eq!(cache.misses(), 0);
+    assert_eq!(cache.query(&(9, 9)), None);
+    assert_eq!(cache.misses(), 1);
 }
 
 /*
@@ -169,16 +1360,347 @@ impl Cache {
     Rc<RefCell<T>>.
 */
 
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::cell::BorrowMutError;
 
+#[derive(Default)]
 pub struct RefCellExample {
     previous: Rc<RefCell<Vec<usize>>>,
     next: Rc<RefCell<Vec<usize>>>,
 }
 impl RefCellExample {
+    pub fn new() -> Self {
+        Default::default()
+    }
     pub fn modify_with_immut_self(&self) {
         self.previous.borrow_mut().push(3);
         self.next.borrow_mut().push(4);
     }
+    // Same, but if either vector is already borrowed (say, by whoever
+    // called us), return an error instead of panicking.
+    // Both borrows are taken before pushing anything, so on an error
+    // neither vector is modified.
+    pub fn try_modify(&self) -> Result<(), BorrowMutError> {
+        let mut previous = self.previous.try_borrow_mut()?;
+        let mut next = self.next.try_borrow_mut()?;
+        previous.push(3);
+        next.push(4);
+        Ok(())
+    }
+    pub fn push_previous(&self, x: usize) {
+        self.previous.borrow_mut().push(x);
+    }
+    pub fn push_next(&self, x: usize) {
+        self.next.borrow_mut().push(x);
+    }
+    // Copies of the current contents. (Returning references instead would
+    // mean returning the Ref guards, keeping both RefCells borrowed.)
+    pub fn snapshot(&self) -> (Vec<usize>, Vec<usize>) {
+        (self.previous.borrow().clone(), self.next.borrow().clone())
+    }
+}
+
+#[test]
+fn test_refcell_example() {
+    let example = RefCellExample::new();
+    assert_eq!(example.snapshot(), (vec![], vec![]));
+    example.push_previous(1);
+    example.push_next(2);
+    example.push_previous(5);
+    assert_eq!(example.snapshot(), (vec![1, 5], vec![2]));
+    example.modify_with_immut_self();
+    assert_eq!(example.snapshot(), (vec![1, 5, 3], vec![2, 4]));
+}
+
+#[test]
+fn test_refcell_try_modify() {
+    let example = RefCellExample::new();
+    assert!(example.try_modify().is_ok());
+
+    // Someone else is looking at next: no panic, and no change
+    let reading = example.next.borrow();
+    assert!(example.try_modify().is_err());
+    drop(reading);
+    assert_eq!(example.snapshot(), (vec![3], vec![4]));
+
+    let _writing = example.previous.borrow_mut();
+    assert!(example.try_modify().is_err());
+}
+
+// The same Rc<RefCell<...>> pattern, made generic. Every handle points at
+// the same Vec: the Rc gives shared ownership, the RefCell lets any of
+// the owners mutate it.
+pub struct SharedList<T> {
+    items: Rc<RefCell<Vec<T>>>,
+}
+impl<T> Default for SharedList<T> {
+    fn default() -> Self {
+        Self { items: Default::default() }
+    }
+}
+impl<T> SharedList<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn push(&self, x: T) {
+        self.items.borrow_mut().push(x);
+    }
+    pub fn pop(&self) -> Option<T> {
+        self.items.borrow_mut().pop()
+    }
+    pub fn len(&self) -> usize {
+        self.items.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.borrow().is_empty()
+    }
+    // Another handle to the same list (not a copy of the list!)
+    pub fn clone_handle(&self) -> SharedList<T> {
+        Self { items: Rc::clone(&self.items) }
+    }
+}
+
+#[test]
+fn test_shared_list() {
+    let list = SharedList::new();
+    let other = list.clone_handle();
+    list.push("a");
+    other.push("b");
+    assert_eq!(list.len(), 2);
+    assert_eq!(other.len(), 2);
+
+    assert_eq!(list.pop(), Some("b"));
+    assert_eq!(other.pop(), Some("a"));
+    assert!(list.is_empty());
+    assert_eq!(other.pop(), None);
+}
+
+/*
+    Weak: breaking Rc cycles
+
+    If a parent has an Rc to its child and the child an Rc to its parent,
+    neither count ever reaches zero and both leak. The fix: only one
+    direction owns. The parent owns its children (Rc), the child merely
+    refers back (Weak). A Weak doesn't keep the value alive; upgrade()
+    gives an Rc if the value still exists, and None otherwise.
+*/
+
+use std::rc::Weak;
+
+pub struct Node {
+    pub value: i32,
+    parent: RefCell<Weak<Node>>,
+    children: RefCell<Vec<Rc<Node>>>,
+}
+impl Node {
+    pub fn new(value: i32) -> Rc<Node> {
+        Rc::new(Node {
+            value,
+            parent: RefCell::new(Weak::new()),
+            children: RefCell::new(Vec::new()),
+        })
+    }
+    // Takes the parent as an Rc, since the child needs a Weak made from it
+    pub fn add_child(self: &Rc<Self>, child: Rc<Node>) {
+        *child.parent.borrow_mut() = Rc::downgrade(self);
+        self.children.borrow_mut().push(child);
+    }
+    pub fn parent(&self) -> Option<Rc<Node>> {
+        self.parent.borrow().upgrade()
+    }
+    pub fn children(&self) -> Vec<Rc<Node>> {
+        self.children.borrow().clone()
+    }
+}
+
+#[test]
+fn test_node_weak_parent() {
+    let root = Node::new(1);
+    let child = Node::new(2);
+    root.add_child(Rc::clone(&child));
+    child.add_child(Node::new(3));
+
+    assert_eq!(child.parent().unwrap().value, 1);
+    assert!(root.parent().is_none());
+    let grandchild = child.children()[0].clone();
+    assert_eq!(grandchild.parent().unwrap().value, 2);
+    // The back-references don't count as owners
+    assert_eq!(Rc::strong_count(&root), 1);
+    assert_eq!(Rc::weak_count(&root), 1);
+
+    let weak_root = Rc::downgrade(&root);
+    let weak_child = Rc::downgrade(&child);
+    let weak_grandchild = Rc::downgrade(&grandchild);
+    drop((child, grandchild));
+    // Still owned by the tree
+    assert!(weak_grandchild.upgrade().is_some());
+
+    // Dropping the root frees the whole tree: nothing leaked in a cycle
+    drop(root);
+    assert!(weak_root.upgrade().is_none());
+    assert!(weak_child.upgrade().is_none());
+    assert!(weak_grandchild.upgrade().is_none());
+}
+
+// Without Weak, cycles do leak, silently. To see it happen, TrackedRc is
+// an Rc that keeps score: each value it allocates is registered in a
+// (per-thread) set, and unregistered when the value is finally dropped.
+// Whatever is still registered after everything has gone out of scope
+// was leaked.
+use std::ops::Deref;
+
+thread_local! {
+    static LIVE_TRACKED: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+    static NEXT_TRACKED: Cell<usize> = const { Cell::new(0) };
+}
+
+// Values allocated through TrackedRc on this thread and not yet dropped
+pub fn live_tracked_rcs() -> usize {
+    LIVE_TRACKED.with(|live| live.borrow().len())
+}
+
+struct Tracked<T> {
+    value: T,
+    id: usize,
+}
+impl<T> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        LIVE_TRACKED.with(|live| live.borrow_mut().remove(&self.id));
+    }
+}
+
+pub struct TrackedRc<T> {
+    rc: Rc<Tracked<T>>,
+}
+impl<T> TrackedRc<T> {
+    pub fn new(value: T) -> Self {
+        let id = NEXT_TRACKED.with(|next| next.replace(next.get() + 1));
+        LIVE_TRACKED.with(|live| live.borrow_mut().insert(id));
+        TrackedRc { rc: Rc::new(Tracked { value, id }) }
+    }
+    pub fn strong_count(this: &Self) -> usize {
+        Rc::strong_count(&this.rc)
+    }
+}
+// Like Rc: cloning only clones the pointer
+impl<T> Clone for TrackedRc<T> {
+    fn clone(&self) -> Self {
+        TrackedRc { rc: Rc::clone(&self.rc) }
+    }
+}
+impl<T> Deref for TrackedRc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.rc.value
+    }
+}
+
+#[test]
+fn test_tracked_rc_cycle_leaks() {
+    struct Link {
+        next: RefCell<Option<TrackedRc<Link>>>,
+    }
+    let before = live_tracked_rcs();
+
+    // No cycle: everything is freed
+    {
+        let a = TrackedRc::new(Link { next: RefCell::new(None) });
+        let b = TrackedRc::new(Link { next: RefCell::new(Some(a.clone())) });
+        assert_eq!(TrackedRc::strong_count(&a), 2);
+        assert_eq!(live_tracked_rcs(), before + 2);
+        drop(b);
+    }
+    assert_eq!(live_tracked_rcs(), before);
+
+    // a -> b -> a: each keeps the other alive
+    {
+        let a = TrackedRc::new(Link { next: RefCell::new(None) });
+        let b = TrackedRc::new(Link { next: RefCell::new(Some(a.clone())) });
+        *a.next.borrow_mut() = Some(b.clone());
+    }
+    assert_eq!(live_tracked_rcs(), before + 2);
+}
+
+// One more Rc<RefCell<...>>: memoizing a recursive function.
+// compute gets the Memoizer itself, so it can recurse through it, e.g.
+// fib(n) asks the memoizer for fib(n - 1) and fib(n - 2). That recursive
+// call happens while the outer call is still running, so call can only
+// take &self -- hence the RefCell. And no borrow may be held while
+// compute runs, or the inner call's borrow_mut would panic.
+// The Rc makes cloned Memoizers share one table.
+pub struct Memoizer<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    table: Rc<RefCell<HashMap<K, V>>>,
+}
+impl<K, V> Default for Memoizer<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self { table: Default::default() }
+    }
+}
+impl<K, V> Clone for Memoizer<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    fn clone(&self) -> Self {
+        Self { table: Rc::clone(&self.table) }
+    }
+}
+impl<K, V> Memoizer<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn call<F: Fn(&Memoizer<K, V>, K) -> V>(&self, k: K, compute: F) -> V {
+        if let Some(v) = self.table.borrow().get(&k) {
+            return v.clone();
+        }
+        // (the borrow above ends here, before computing)
+        let v = compute(self, k.clone());
+        self.table.borrow_mut().insert(k, v.clone());
+        v
+    }
+    // Number of results remembered so far
+    pub fn len(&self) -> usize {
+        self.table.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.table.borrow().is_empty()
+    }
+}
+
+#[test]
+fn test_memoizer_fib() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COMPUTED: AtomicUsize = AtomicUsize::new(0);
+    fn fib(memo: &Memoizer<u64, u64>, n: u64) -> u64 {
+        COMPUTED.fetch_add(1, Ordering::SeqCst);
+        if n < 2 {
+            n
+        } else {
+            memo.call(n - 1, fib) + memo.call(n - 2, fib)
+        }
+    }
+
+    // Without memoization this would take hundreds of millions of calls
+    let memo = Memoizer::new();
+    assert_eq!(memo.call(40, fib), 102_334_155);
+    // fib(0) to fib(40), each computed exactly once
+    assert_eq!(COMPUTED.load(Ordering::SeqCst), 41);
+    assert_eq!(memo.len(), 41);
+
+    // Already known, also through another handle
+    let other = memo.clone();
+    assert_eq!(other.call(30, fib), 832_040);
+    assert_eq!(COMPUTED.load(Ordering::SeqCst), 41);
 }
@@ -110,6 +110,65 @@ pub fn call_unsafe_bloc() {
 // it's accessed to figure out which enum variant it is.
 // SO: accessing a union is unsafe.
 
+// Unless we keep the label ourselves, that is. This is what an enum does
+// under the hood, spelled out: a tag saying which field was written last,
+// checked before every read, so the unsafe read is never of the wrong type.
+#[derive(Clone, Copy)]
+union IntOrFloat {
+    i: i32,
+    f: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Int,
+    Float,
+}
+
+#[derive(Clone, Copy)]
+pub struct TaggedUnion {
+    tag: Tag,
+    value: IntOrFloat,
+}
+impl TaggedUnion {
+    pub fn from_i32(i: i32) -> Self {
+        Self { tag: Tag::Int, value: IntOrFloat { i } }
+    }
+    pub fn from_f32(f: f32) -> Self {
+        Self { tag: Tag::Float, value: IntOrFloat { f } }
+    }
+    pub fn tag(&self) -> Tag {
+        self.tag
+    }
+    pub fn as_i32(&self) -> Option<i32> {
+        match self.tag {
+            // Safety: the tag says i is the field that was written
+            Tag::Int => Some(unsafe { self.value.i }),
+            Tag::Float => None,
+        }
+    }
+    pub fn as_f32(&self) -> Option<f32> {
+        match self.tag {
+            Tag::Float => Some(unsafe { self.value.f }),
+            Tag::Int => None,
+        }
+    }
+}
+
+#[test]
+fn test_tagged_union() {
+    let i = TaggedUnion::from_i32(42);
+    assert_eq!(i.tag(), Tag::Int);
+    assert_eq!(i.as_i32(), Some(42));
+    // Reading the bits of 42 as a float would be garbage
+    assert_eq!(i.as_f32(), None);
+
+    let f = TaggedUnion::from_f32(1.5);
+    assert_eq!(f.tag(), Tag::Float);
+    assert_eq!(f.as_f32(), Some(1.5));
+    assert_eq!(f.as_i32(), None);
+}
+
 // Undefined behavior
 // Where does undefined behavior come from?
 // UB is a language level idea, language semantics defined expected behavior,
@@ -202,6 +261,50 @@ pub fn raw_pointers() {
     // No pointer arithmetic operators. methods offset and wrappering_offset.
 }
 
+// Note that 'x as *mut i64' above turns the *value* of x into an address,
+// which points at nothing in particular. Getting a pointer to x has to go
+// through a reference, which is what this small wrapper enforces: the
+// only way to build a non-null TypedPtr is from an actual reference.
+pub struct TypedPtr<T>(*const T);
+impl<T> TypedPtr<T> {
+    pub fn from_ref(r: &T) -> Self {
+        TypedPtr(r)
+    }
+    pub fn null() -> Self {
+        TypedPtr(std::ptr::null())
+    }
+    pub fn is_null(&self) -> bool {
+        self.0.is_null()
+    }
+    /// # Safety
+    /// The value the pointer was made from must still be alive (and not
+    /// mutated while the returned reference is in use), and the pointer
+    /// must not be null.
+    pub unsafe fn deref(&self) -> &T {
+        &*self.0
+    }
+}
+// Copying a pointer is always fine, whether or not T is Copy
+impl<T> Clone for TypedPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for TypedPtr<T> {}
+
+#[test]
+fn test_typed_ptr() {
+    let x: i64 = 0xFFFF32ADF;
+    let p = TypedPtr::from_ref(&x);
+    assert!(!p.is_null());
+    let q = p;
+    unsafe {
+        assert_eq!(*p.deref(), 0xFFFF32ADF);
+        assert_eq!(*q.deref(), x);
+    }
+    assert!(TypedPtr::<i64>::null().is_null());
+}
+
 // Useful fuctions:
 // null and null_mut
 // https://doc.rust-lang.org/std/ptr/fn.null_mut.html
@@ -211,11 +314,21 @@ pub fn raw_pointers() {
 
 type TimeT = i64;
 
+// Declaring a C function: the signature has to match the C one exactly,
+// the compiler has no way to check it. That's why every call is unsafe.
+mod ffi {
+    use super::TimeT;
+    extern "C" {
+        pub fn time(t: *mut TimeT) -> TimeT;
+    }
+}
+
 /// Documentation
 /// # Safety
-/// This function is only safe if the caller ensures ....
-pub unsafe fn time(_time: *mut TimeT) -> TimeT {
-    unimplemented!()
+/// This function is only safe if the caller ensures that `time` is either
+/// null or valid to write a `TimeT` to.
+pub unsafe fn time(time: *mut TimeT) -> TimeT {
+    ffi::time(time)
 }
 
 use std::ptr::null_mut;
@@ -224,6 +337,25 @@ pub fn call_time() {
     let _t = unsafe { time(null_mut()) };
 }
 
+// The safe wrapper: with a null pointer, time() only returns the result
+pub fn now_unix() -> i64 {
+    unsafe { time(null_mut()) }
+}
+
+#[test]
+fn test_time() {
+    // 2020-01-01 00:00:00 UTC
+    const START_OF_2020: i64 = 1_577_836_800;
+    let now = now_unix();
+    assert!(now > START_OF_2020);
+
+    // Writing through the pointer as well
+    let mut written: TimeT = 0;
+    let returned = unsafe { time(&mut written) };
+    assert_eq!(written, returned);
+    assert!(returned >= now);
+}
+
 /*
     System calls
     Rust: Nix
@@ -267,12 +399,765 @@ pub fn test_fork() {
     // assert!(false);
 }
 
+// A safer version of the above. The raw fork() hands back a bare PID,
+// which is easy to forget about; a child that exits and is never waited on
+// stays around as a zombie. Instead, wrap the PID in a handle that reaps
+// the child when it goes out of scope (RAII, like File closing itself).
+
+use nix::sys::wait::{self, WaitStatus};
+use nix::unistd::Pid;
+use std::cell::Cell;
+use std::io;
+
+// nix has its own error type; the rest of the world speaks io::Error
+fn nix_to_io(err: nix::Error) -> io::Error {
+    match err {
+        nix::Error::Sys(errno) => errno.into(),
+        other => io::Error::other(other),
+    }
+}
+
+// How the Fork::Child side should finish. Unlike std::process::exit, _exit
+// doesn't run atexit handlers or flush buffers the child inherited from
+// the parent, which would otherwise happen twice.
+pub fn exit_child(code: i32) -> ! {
+    unsafe { nix::libc::_exit(code) }
+}
+
+// How a child process ended (or paused), without the extra cases
+// WaitStatus has for ptrace and non-blocking waits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildExit {
+    // Exited normally with this exit code
+    Exited(i32),
+    // Killed by this signal number
+    Signaled(i32),
+    // Stopped (not ended!) by this signal number
+    Stopped(i32),
+}
+impl ChildExit {
+    fn from_status(status: WaitStatus) -> Option<Self> {
+        match status {
+            WaitStatus::Exited(_, code) => Some(ChildExit::Exited(code)),
+            WaitStatus::Signaled(_, sig, _) => {
+                Some(ChildExit::Signaled(sig as i32))
+            }
+            WaitStatus::Stopped(_, sig) => Some(ChildExit::Stopped(sig as i32)),
+            _ => None,
+        }
+    }
+}
+
+// Block until the child exits or is stopped.
+// This takes the Child handle rather than a bare pid, so that a child
+// that exited is marked as reaped: its pid may be reused right away, and
+// the handle must not wait on it (or signal it) again.
+// A stopped child is still around, so it is still reaped on drop.
+pub fn wait_child(child: &Child) -> io::Result<ChildExit> {
+    loop {
+        let status =
+            wait::waitpid(child.pid, Some(wait::WaitPidFlag::WUNTRACED))
+                .map_err(nix_to_io)?;
+        if let Some(exit) = ChildExit::from_status(status) {
+            if let ChildExit::Exited(_) | ChildExit::Signaled(_) = exit {
+                child.reaped.set(true);
+            }
+            return Ok(exit);
+        }
+    }
+}
+
+pub struct Child {
+    pid: Pid,
+    reaped: Cell<bool>,
+}
+impl Child {
+    fn new(pid: Pid) -> Self {
+        Self { pid, reaped: Cell::new(false) }
+    }
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+    // Block until the child exits.
+    // Only mark it reaped once waitpid succeeds: if it fails (e.g. EINTR),
+    // the child is still there, and drop has to wait on it instead.
+    pub fn wait(self) -> io::Result<WaitStatus> {
+        let status = wait::waitpid(self.pid, None).map_err(nix_to_io)?;
+        self.reaped.set(true);
+        Ok(status)
+    }
+    // Same, but reporting how it ended (see wait_child)
+    pub fn wait_exit(&self) -> io::Result<ChildExit> {
+        wait_child(self)
+    }
+    // Once the child is reaped its pid is free again, and may already
+    // belong to some unrelated process, so refuse to signal it.
+    pub fn kill(&self, sig: Signal) -> io::Result<()> {
+        if self.reaped.get() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "child was already reaped",
+            ));
+        }
+        signal::kill(self.pid, sig).map_err(nix_to_io)
+    }
+}
+// If nobody waited on the child, wait on it now so that it doesn't become
+// a zombie. Note this blocks until the child exits, so a child that may
+// run forever should be killed first.
+impl Drop for Child {
+    fn drop(&mut self) {
+        if !self.reaped.get() {
+            let _ = wait::waitpid(self.pid, None);
+        }
+    }
+}
+
+pub enum Fork {
+    Parent(Child),
+    Child,
+}
+
+// Careful: this is only "safe" in the sense that it has a safe API.
+// If other threads are running, the child gets a copy of memory where
+// they might have been in the middle of something (e.g. holding a lock),
+// so the child should stick to simple things and then exit.
+pub fn safe_fork() -> io::Result<Fork> {
+    match unsafe { unistd::fork() }.map_err(nix_to_io)? {
+        ForkResult::Parent { child } => Ok(Fork::Parent(Child::new(child))),
+        ForkResult::Child => Ok(Fork::Child),
+    }
+}
+
+// Most of the time we just want to run some code in another process.
+// The closure only ever runs in the child, and the child exits as soon as
+// it returns, so the caller only ever continues in the parent.
+// A panic in f must not unwind into the caller's code (which would then
+// carry on running in the child), so it is caught and turned into exit
+// code 101, the same one a panicking Rust program exits with.
+pub fn run_in_child<F: FnOnce()>(f: F) -> io::Result<Child> {
+    match safe_fork()? {
+        Fork::Parent(child) => Ok(child),
+        Fork::Child => {
+            let result =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            exit_child(if result.is_ok() { 0 } else { 101 })
+        }
+    }
+}
+
+// To get data back from the child, use a pipe: whatever is written to the
+// write end comes out of the read end, even across processes.
+// Both ends are plain Files underneath, so they are closed when dropped.
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+pub struct PipeReader(File);
+pub struct PipeWriter(File);
+
+pub fn pipe() -> io::Result<(PipeReader, PipeWriter)> {
+    let (read_fd, write_fd) = unistd::pipe().map_err(nix_to_io)?;
+    // Safety: the fds were just created and nothing else owns them
+    unsafe {
+        Ok((
+            PipeReader(File::from_raw_fd(read_fd)),
+            PipeWriter(File::from_raw_fd(write_fd)),
+        ))
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+impl AsRawFd for PipeReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+impl AsRawFd for PipeWriter {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+// Each process closes the end it doesn't use. This matters: a reader only
+// sees end-of-file once *every* copy of the write end is closed, so if the
+// parent kept its copy, reading until EOF would hang forever.
+pub fn fork_with_pipe<F: FnOnce(PipeWriter)>(
+    f: F,
+) -> io::Result<(Child, PipeReader)> {
+    let (reader, writer) = pipe()?;
+    let reader_fd = reader.as_raw_fd();
+    // The closure owns the writer, so the parent's copy is closed as soon
+    // as the closure is dropped, right after the fork.
+    let child = run_in_child(move || {
+        // The child's copy of reader is never dropped (the child just
+        // exits), so close its fd by hand.
+        let _ = unistd::close(reader_fd);
+        f(writer);
+    })?;
+    Ok((child, reader))
+}
+
+// Forked children inherit every open file descriptor of the test process,
+// including pipes belonging to other tests running in parallel. Tests
+// that fork take this lock so they don't hold up each other's pipes.
+#[cfg(test)]
+fn fork_test_lock() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[test]
+fn test_safe_fork() {
+    let _guard = fork_test_lock();
+    match safe_fork().unwrap() {
+        Fork::Parent(child) => {
+            let pid = child.pid();
+            assert_eq!(child.wait().unwrap(), WaitStatus::Exited(pid, 0));
+        }
+        Fork::Child => exit_child(0),
+    }
+
+    // Dropping the handle reaps the child: afterwards there is nothing
+    // left to wait for.
+    match safe_fork().unwrap() {
+        Fork::Parent(child) => {
+            let pid = child.pid();
+            drop(child);
+            assert!(wait::waitpid(pid, None).is_err());
+        }
+        Fork::Child => exit_child(0),
+    }
+}
+
+#[test]
+fn test_run_in_child() {
+    let _guard = fork_test_lock();
+    let (read_fd, write_fd) = unistd::pipe().unwrap();
+    let mut reader = unsafe { File::from_raw_fd(read_fd) };
+    let mut writer = unsafe { File::from_raw_fd(write_fd) };
+
+    let child = run_in_child(|| {
+        writer.write_all(b"hello from child").unwrap();
+    })
+    .unwrap();
+    // Close the parent's write end, or read_to_string never sees EOF
+    drop(writer);
+
+    let mut message = String::new();
+    reader.read_to_string(&mut message).unwrap();
+    assert_eq!(message, "hello from child");
+    let pid = child.pid();
+    assert_eq!(child.wait().unwrap(), WaitStatus::Exited(pid, 0));
+
+    // A panic in the child is reported through its exit code
+    let child = run_in_child(|| panic!("child panicked")).unwrap();
+    let pid = child.pid();
+    assert_eq!(child.wait().unwrap(), WaitStatus::Exited(pid, 101));
+}
+
+#[test]
+fn test_fork_with_pipe() {
+    let _guard = fork_test_lock();
+    let (child, mut reader) = fork_with_pipe(|mut writer| {
+        writer.write_all(&[1, 2, 3]).unwrap();
+        writer.write_all(b"done").unwrap();
+    })
+    .unwrap();
+
+    let mut received = Vec::new();
+    reader.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"\x01\x02\x03done");
+    let pid = child.pid();
+    assert_eq!(child.wait().unwrap(), WaitStatus::Exited(pid, 0));
+}
+
+#[test]
+fn test_wait_child() {
+    let _guard = fork_test_lock();
+    let child = run_in_child(|| ()).unwrap();
+    assert_eq!(child.wait_exit().unwrap(), ChildExit::Exited(0));
+
+    let child = run_in_child(|| exit_child(42)).unwrap();
+    assert_eq!(wait_child(&child).unwrap(), ChildExit::Exited(42));
+    // The handle knows it was reaped, so it won't touch the pid again
+    // (neither here nor when dropped)
+    assert!(child.kill(Signal::SIGKILL).is_err());
+    drop(child);
+
+    let child = run_in_child(|| loop {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    })
+    .unwrap();
+    child.kill(Signal::SIGKILL).unwrap();
+    assert_eq!(
+        child.wait_exit().unwrap(),
+        ChildExit::Signaled(Signal::SIGKILL as i32)
+    );
+}
+
+#[test]
+fn test_kill_after_reap() {
+    let _guard = fork_test_lock();
+    let child = run_in_child(|| ()).unwrap();
+    assert_eq!(child.wait_exit().unwrap(), ChildExit::Exited(0));
+    let err = child.kill(Signal::SIGKILL).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+// The polite way to stop a child: ask with SIGTERM, which it may catch to
+// clean up (or ignore), and only if it is still around after the grace
+// period, SIGKILL it, which can't be caught or ignored.
+// Checking whether it's done uses waitpid with WNOHANG, which returns
+// right away instead of blocking.
+// A child that was already reaped can't be stopped again: that is an
+// error (from kill), and its pid is never signaled.
+use std::time::{Duration, Instant};
+
+pub fn terminate(child: &Child, grace: Duration) -> io::Result<ChildExit> {
+    child.kill(Signal::SIGTERM)?;
+    let start = Instant::now();
+    loop {
+        let status = wait::waitpid(child.pid, Some(wait::WaitPidFlag::WNOHANG))
+            .map_err(nix_to_io)?;
+        if let Some(exit @ (ChildExit::Exited(_) | ChildExit::Signaled(_))) =
+            ChildExit::from_status(status)
+        {
+            child.reaped.set(true);
+            return Ok(exit);
+        }
+        if start.elapsed() >= grace {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    child.kill(Signal::SIGKILL)?;
+    child.wait_exit()
+}
+
+#[test]
+fn test_terminate() {
+    let _guard = fork_test_lock();
+    let sleep_forever = || loop {
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    // Goes away on SIGTERM
+    let child = run_in_child(sleep_forever).unwrap();
+    let exit = terminate(&child, Duration::from_secs(5)).unwrap();
+    assert_eq!(exit, ChildExit::Signaled(Signal::SIGTERM as i32));
+
+    // Ignores SIGTERM, so needs SIGKILL
+    let (child, mut reader) = fork_with_pipe(|mut writer| {
+        unsafe { signal::signal(Signal::SIGTERM, SigHandler::SigIgn) }.unwrap();
+        // Tell the parent SIGTERM is ignored now, to avoid racing it
+        writer.write_all(b"ready").unwrap();
+        drop(writer);
+        sleep_forever()
+    })
+    .unwrap();
+    let mut ready = [0; 5];
+    reader.read_exact(&mut ready).unwrap();
+    let exit = terminate(&child, Duration::from_millis(50)).unwrap();
+    assert_eq!(exit, ChildExit::Signaled(Signal::SIGKILL as i32));
+
+    // Already gone
+    let err = terminate(&child, Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+// Turn the current process into a background daemon, the classic way.
+// Careful: the calling process exits! Only the final grandchild returns.
+//  1. fork, and the original process exits, once the child below has.
+//     The child is not a process group leader, which setsid() requires.
+//  2. setsid(): start a new session, leaving the controlling terminal.
+//  3. fork again, and the session leader exits. The grandchild is not a
+//     session leader so it can never get a terminal back, and as an orphan
+//     it is adopted by init, which reaps it when it is done.
+//  4. Don't keep the old working directory busy, and point stdin, stdout
+//     and stderr at /dev/null since there is no terminal anymore.
+// Only the first fork can fail with an Err, still in the caller's own
+// process. A failure after it must not return, or that copy would go on
+// running the caller's code as a second instance: instead it exits with
+// code 1, and the original process exits with the intermediate child's
+// code, so a failed setsid or second fork still shows up as nonzero.
+pub fn daemonize() -> io::Result<()> {
+    if let Fork::Parent(child) = safe_fork()? {
+        let code = match child.wait_exit() {
+            Ok(ChildExit::Exited(code)) => code,
+            _ => 1,
+        };
+        exit_child(code);
+    }
+    or_exit(unistd::setsid().map_err(nix_to_io));
+    if let Fork::Parent(_) = or_exit(safe_fork()) {
+        exit_child(0);
+    }
+    or_exit(unistd::chdir("/").map_err(nix_to_io));
+    let dev_null = or_exit(
+        std::fs::OpenOptions::new().read(true).write(true).open("/dev/null"),
+    );
+    for fd in 0..3 {
+        or_exit(unistd::dup2(dev_null.as_raw_fd(), fd).map_err(nix_to_io));
+    }
+    Ok(())
+}
+
+// Auxiliary function for daemonize: the value, or exit code 1 on error
+fn or_exit<T>(result: io::Result<T>) -> T {
+    result.unwrap_or_else(|_| exit_child(1))
+}
+
+#[test]
+fn test_daemonize() {
+    let _guard = fork_test_lock();
+    let pidfile = std::env::temp_dir()
+        .join(format!("lecture8-daemon-{}.pid", unistd::getpid()));
+    let _ = std::fs::remove_file(&pidfile);
+
+    let path = pidfile.clone();
+    let child = run_in_child(move || {
+        daemonize().unwrap();
+        std::fs::write(&path, unistd::getpid().to_string()).unwrap();
+    })
+    .unwrap();
+    let child_pid = child.pid();
+    // The process we forked exits right away; the daemon lives on
+    assert_eq!(child.wait_exit().unwrap(), ChildExit::Exited(0));
+
+    let start = Instant::now();
+    let daemon_pid = loop {
+        let contents = std::fs::read_to_string(&pidfile).unwrap_or_default();
+        if let Ok(pid) = contents.parse::<i32>() {
+            break pid;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "pidfile never appeared"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert_ne!(daemon_pid, child_pid.as_raw());
+    std::fs::remove_file(&pidfile).unwrap();
+}
+
+/*
+    Signal handlers
+
+    A signal handler can interrupt the program anywhere, e.g. in the middle
+    of malloc() while it holds a lock, so there is very little a handler
+    may safely do: no allocating, no locking, no printing.
+    Setting an atomic flag is fine though. So the handler below only
+    records that the signal arrived, and the program checks for it later
+    with pending(), or run_pending() to call the registered functions
+    from normal (non-handler) code.
+*/
+
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+// Indexed by signal number (the standard signals are 1 to 31)
+const NUM_SIGNALS: usize = 32;
+type Callbacks = [Option<fn()>; NUM_SIGNALS];
+static PENDING: [AtomicBool; NUM_SIGNALS] =
+    [const { AtomicBool::new(false) }; NUM_SIGNALS];
+static CALLBACKS: Mutex<Callbacks> = Mutex::new([None; NUM_SIGNALS]);
+
+extern "C" fn record_signal(sig: nix::libc::c_int) {
+    if let Some(flag) = PENDING.get(sig as usize) {
+        flag.store(true, Ordering::SeqCst);
+    }
+}
+
+// f is not called from the handler itself, but by run_pending().
+// The callback is only stored once sigaction has succeeded, so a failed
+// install leaves the previous callback in place. (A signal arriving in
+// between just waits as pending until run_pending finds f.)
+pub fn install_handler(sig: Signal, f: fn()) -> io::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(record_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // Safety: record_signal only touches an atomic
+    unsafe { signal::sigaction(sig, &action) }.map_err(nix_to_io)?;
+    CALLBACKS.lock().unwrap()[sig as usize] = Some(f);
+    Ok(())
+}
+
+// Whether sig arrived since the last check (clears the flag)
+pub fn pending(sig: Signal) -> bool {
+    PENDING[sig as usize].swap(false, Ordering::SeqCst)
+}
+
+// Call the registered function of every signal that arrived since the
+// last check. Returns how many were called.
+pub fn run_pending() -> usize {
+    let callbacks = *CALLBACKS.lock().unwrap();
+    let mut called = 0;
+    for (num, callback) in callbacks.iter().enumerate() {
+        if let Some(f) = callback {
+            if PENDING[num].swap(false, Ordering::SeqCst) {
+                f();
+                called += 1;
+            }
+        }
+    }
+    called
+}
+
+#[test]
+fn test_signal_handler() {
+    static CALLED: AtomicBool = AtomicBool::new(false);
+    fn on_usr1() {
+        CALLED.store(true, Ordering::SeqCst);
+    }
+
+    install_handler(Signal::SIGUSR1, on_usr1).unwrap();
+    assert!(!pending(Signal::SIGUSR1));
+    // raise() delivers the signal to this thread before returning
+    signal::raise(Signal::SIGUSR1).unwrap();
+    assert!(pending(Signal::SIGUSR1));
+    assert!(!pending(Signal::SIGUSR1));
+
+    signal::raise(Signal::SIGUSR1).unwrap();
+    assert_eq!(run_pending(), 1);
+    assert!(CALLED.load(Ordering::SeqCst));
+    assert_eq!(run_pending(), 0);
+
+    // SIGKILL can't be caught, so installing fails and stores nothing
+    assert!(install_handler(Signal::SIGKILL, on_usr1).is_err());
+    assert!(CALLBACKS.lock().unwrap()[Signal::SIGKILL as usize].is_none());
+}
+
 // This is for low-level concurrent programming with processes
 // But you don't have to do this if you want to use a higher-level library
 // for example to run a bunch of stuff in parallel:
 // Rayon: https://crates.io/crates/rayon
 // offers: .par_iter() similar to .iter()
 
+// Still, here is a (very) poor man's version using processes: one child
+// per item. Processes don't share memory, so each child sends its result
+// back through a pipe, serialized. Since a pipe is just a stream of bytes,
+// each message is prefixed with its length so the reader knows where it
+// ends.
+fn write_message<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    w.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    w.write_all(bytes)?;
+    w.flush()
+}
+
+fn read_message<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0; 8];
+    r.read_exact(&mut len)?;
+    let mut bytes = vec![0; u64::from_le_bytes(len) as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+
+// If a child fails (e.g. f panics), only its own slot is an error
+#[cfg(feature = "serde")]
+pub fn parallel_map<T, R, F>(items: Vec<T>, f: F) -> Vec<io::Result<R>>
+where
+    F: Fn(T) -> R,
+    R: Serialize + DeserializeOwned,
+{
+    // Start all the children first, so that they run at the same time
+    let children: Vec<_> = items
+        .into_iter()
+        .map(|item| {
+            fork_with_pipe(|mut writer| {
+                let bytes = serde_json::to_vec(&f(item)).unwrap();
+                write_message(&mut writer, &bytes).unwrap();
+            })
+        })
+        .collect();
+    children
+        .into_iter()
+        .map(|forked| {
+            let (child, mut reader) = forked?;
+            let message = read_message(&mut reader);
+            let exit = child.wait_exit()?;
+            if exit != ChildExit::Exited(0) {
+                return Err(io::Error::other(format!(
+                    "child process failed: {:?}",
+                    exit
+                )));
+            }
+            Ok(serde_json::from_slice(&message?)?)
+        })
+        .collect()
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_parallel_map() {
+    let _guard = fork_test_lock();
+    let squares = parallel_map(vec![1, 2, 3, 4, 5], |x: u64| x * x);
+    let squares: Vec<u64> = squares.into_iter().map(Result::unwrap).collect();
+    assert_eq!(squares, vec![1, 4, 9, 16, 25]);
+
+    let results = parallel_map(vec![1, 2, 3], |x: u64| {
+        if x == 2 {
+            panic!("no twos allowed");
+        }
+        format!("item {}", x)
+    });
+    assert_eq!(results[0].as_ref().unwrap(), "item 1");
+    assert!(results[1].is_err());
+    assert_eq!(results[2].as_ref().unwrap(), "item 3");
+}
+
+// Forking for every item is expensive. A pool forks n workers once, and
+// then keeps sending them work. We can't send a closure to another process,
+// so every worker runs the same function f, and a task is just a number
+// saying what to run it on. Each worker has two pipes: tasks go in as 8
+// byte numbers, results come back as length-prefixed messages.
+// Each worker gets one task at a time, so results are easy to match up.
+use std::collections::VecDeque;
+
+struct Worker {
+    // Dropping tasks first closes the pipe, which is the worker's cue to
+    // exit; only then does dropping child wait for it.
+    tasks: PipeWriter,
+    results: PipeReader,
+    child: Child,
+    current: Option<usize>,
+}
+
+pub struct ForkPool {
+    workers: Vec<Worker>,
+    queue: VecDeque<usize>,
+}
+
+fn worker_loop(
+    mut tasks: PipeReader,
+    mut results: PipeWriter,
+    f: fn(usize) -> Vec<u8>,
+) {
+    let mut task = [0; 8];
+    // Until the parent closes the task pipe
+    while tasks.read_exact(&mut task).is_ok() {
+        let result = f(u64::from_le_bytes(task) as usize);
+        if write_message(&mut results, &result).is_err() {
+            break;
+        }
+    }
+}
+
+impl ForkPool {
+    pub fn new(n: usize, f: fn(usize) -> Vec<u8>) -> io::Result<Self> {
+        assert!(n > 0, "ForkPool needs at least one worker");
+        let mut workers: Vec<Worker> = Vec::with_capacity(n);
+        for _ in 0..n {
+            let (task_reader, task_writer) = pipe()?;
+            let (result_reader, result_writer) = pipe()?;
+            match safe_fork()? {
+                Fork::Parent(child) => {
+                    drop(task_reader);
+                    drop(result_writer);
+                    workers.push(Worker {
+                        tasks: task_writer,
+                        results: result_reader,
+                        child,
+                        current: None,
+                    });
+                }
+                Fork::Child => {
+                    drop(task_writer);
+                    drop(result_reader);
+                    // The pipes to the workers forked before this one were
+                    // copied too. Close them, otherwise those workers would
+                    // never see their task pipe close. Their Child handles
+                    // belong to the parent, so don't wait on them here.
+                    for worker in workers.drain(..) {
+                        let Worker { tasks, results, child, .. } = worker;
+                        drop(tasks);
+                        drop(results);
+                        std::mem::forget(child);
+                    }
+                    let result = std::panic::catch_unwind(|| {
+                        worker_loop(task_reader, result_writer, f)
+                    });
+                    exit_child(if result.is_ok() { 0 } else { 101 })
+                }
+            }
+        }
+        Ok(Self { workers, queue: VecDeque::new() })
+    }
+
+    pub fn submit(&mut self, task: usize) {
+        self.queue.push_back(task);
+    }
+
+    // Run all submitted tasks, returning (task, result) pairs in the order
+    // they finished.
+    pub fn collect(&mut self) -> io::Result<Vec<(usize, Vec<u8>)>> {
+        let mut results = Vec::new();
+        loop {
+            // Hand out tasks to idle workers
+            for worker in
+                self.workers.iter_mut().filter(|w| w.current.is_none())
+            {
+                match self.queue.pop_front() {
+                    Some(task) => {
+                        worker.tasks.write_all(&(task as u64).to_le_bytes())?;
+                        worker.current = Some(task);
+                    }
+                    None => break,
+                }
+            }
+            let mut any_busy = false;
+            for worker in self.workers.iter_mut() {
+                if let Some(task) = worker.current.take() {
+                    results.push((task, read_message(&mut worker.results)?));
+                    any_busy = true;
+                }
+            }
+            if !any_busy {
+                return Ok(results);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fork_pool() {
+    let _guard = fork_test_lock();
+    let mut pool =
+        ForkPool::new(3, |i| (i * i).to_string().into_bytes()).unwrap();
+    for task in 0..10 {
+        pool.submit(task);
+    }
+    let mut results = pool.collect().unwrap();
+    assert_eq!(results.len(), 10);
+    results.sort();
+    for (i, (task, result)) in results.into_iter().enumerate() {
+        assert_eq!(task, i);
+        assert_eq!(String::from_utf8(result).unwrap(), (i * i).to_string());
+    }
+
+    // The same workers take more work
+    pool.submit(100);
+    assert_eq!(pool.collect().unwrap(), vec![(100, b"10000".to_vec())]);
+    assert!(pool.collect().unwrap().is_empty());
+}
+
 // FFI
 // Most languages interface through C.
 
@@ -283,18 +1168,51 @@ pub fn test_fork() {
 
 // Imagine you have a nice rust function like this:
 // How do you call it from C?
-// pub fn fizz_buzz(){
-//     for i in 0..100{
-//         if i % 3 == 0 && i % 5 == 0 {
-//             println!("{}: fizzbuzz", i);
-//         }
-//         if i % 3 == 0 {
-//             println!("{}: fizz", i);
-//         }
-//         if i % 5 == 0 {
-//             println!("{}: buzz", i);
-//         }
-//     }
-
-//     return;
-// }
+// Answer: give it the C calling convention (extern "C") and keep its name
+// as-is in the compiled library (#[no_mangle], normally Rust mangles names
+// to make them unique). Then from C, it is just
+//     void fizz_buzz(void);
+//
+// Also note the else ifs: multiples of 15 print only fizzbuzz, not also a
+// separate fizz and buzz line.
+// Panicking across the FFI boundary would abort the process, so write
+// errors are ignored rather than unwrapped.
+#[no_mangle]
+pub extern "C" fn fizz_buzz() {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for i in 0..100 {
+        let _ = if i % 3 == 0 && i % 5 == 0 {
+            writeln!(out, "{}: fizzbuzz", i)
+        } else if i % 3 == 0 {
+            writeln!(out, "{}: fizz", i)
+        } else if i % 5 == 0 {
+            writeln!(out, "{}: buzz", i)
+        } else {
+            Ok(())
+        };
+    }
+    let _ = out.flush();
+}
+
+#[test]
+fn test_fizz_buzz() {
+    // The test harness only captures println!, so run it in a child whose
+    // stdout (fd 1) is a pipe back to us.
+    let _guard = fork_test_lock();
+    let (child, mut reader) = fork_with_pipe(|writer| {
+        unistd::dup2(writer.as_raw_fd(), 1).unwrap();
+        fizz_buzz();
+    })
+    .unwrap();
+    let mut output = String::new();
+    reader.read_to_string(&mut output).unwrap();
+    assert_eq!(child.wait_exit().unwrap(), ChildExit::Exited(0));
+
+    let lines: Vec<&str> = output.lines().collect();
+    let pos = lines.iter().position(|&line| line.starts_with("15:")).unwrap();
+    assert_eq!(lines[pos], "15: fizzbuzz");
+    assert_eq!(lines[pos + 1], "18: fizz");
+    assert_eq!(lines.iter().filter(|line| line.starts_with("15:")).count(), 1);
+    assert_eq!(lines[..3], ["0: fizzbuzz", "3: fizz", "5: buzz"]);
+}